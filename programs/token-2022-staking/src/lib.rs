@@ -1,20 +1,32 @@
 use anchor_lang::prelude::*;
 
+use anchor_lang::solana_program::program::invoke_signed;
+
 use anchor_spl::{
     associated_token::AssociatedToken,
-    token_2022_extensions::transfer_fee::{transfer_checked_with_fee, TransferCheckedWithFee},
-    token_interface::{Mint, Token2022, TokenAccount},
+    token_2022_extensions::transfer_fee::{
+        harvest_withheld_tokens_to_mint, withdraw_withheld_tokens_from_mint,
+        HarvestWithheldTokensToMint, WithdrawWithheldTokensFromMint,
+    },
+    token_interface::{burn, Burn, Mint, TokenAccount, TokenInterface},
+};
+
+use spl_token_2022::{
+    amount_to_ui_amount_string_trimmed,
+    extension::{
+        interest_bearing_mint::InterestBearingConfig,
+        transfer_fee::{instruction as transfer_fee_instruction, TransferFeeConfig},
+        transfer_hook, BaseStateWithExtensions, StateWithExtensions,
+    },
+    instruction as token_instruction,
 };
 
-use spl_token_2022::extension::{transfer_fee::TransferFeeConfig, StateWithExtensions};
+use spl_transfer_hook_interface::onchain::add_extra_accounts_for_execute_cpi;
 
 declare_id!("DFgDg9Mc69FcWcsSHTPimE35x3W8uGdAZGCGWSeseFWh");
 
 #[program]
 pub mod token_2022_staking {
-
-    use spl_token_2022::extension::BaseStateWithExtensions;
-
     use super::*;
 
     /**
@@ -22,17 +34,47 @@ pub mod token_2022_staking {
      */
     pub fn initialize(
         ctx: Context<Initialize>,
+        pool_id: u64,
         min_stake_period: i64,
-        decimals: u8,
-        tax_percentage: u8,
+        reward_rate_bps: u16,
     ) -> Result<()> {
         let config = &mut ctx.accounts.config;
 
         config.authority = *ctx.accounts.authority.key;
+        config.pending_authority = None;
         config.min_stake_period = min_stake_period;
         config.token_mint_address = *ctx.accounts.token_mint.to_account_info().key;
-        config.decimals = decimals;
-        config.tax_percentage = tax_percentage;
+        config.pool_id = pool_id;
+        config.bump = ctx.bumps.config;
+        config.stake_vault_bump = ctx.bumps.stake_vault;
+        config.reward_vault_bump = ctx.bumps.reward_vault;
+        config.reward_rate_bps = reward_rate_bps;
+        config.acc_reward_per_share = 0;
+        config.last_reward_time = Clock::get()?.unix_timestamp;
+        config.total_staked = 0;
+        config.total_shares = 0;
+        config.compounded_principal = 0;
+        config.lock_tiers = Vec::new();
+        config.reward_budget = 0;
+        config.reward_liabilities = 0;
+        config.wind_down = false;
+        config.paused = 0;
+        config.emergency = false;
+        config.early_unstake_penalty_bps = 0;
+        config.penalty_linear_decay = false;
+        config.penalty_destination = PenaltyDestination::RewardVault;
+        config.penalty_treasury = Pubkey::default();
+        config.unstake_cooldown = 0;
+        config.total_pending_withdrawals = 0;
+        config.apy_on_ui_amount = false;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            config.last_reward_time
+        ));
+
         Ok(())
     }
 
@@ -50,81 +92,109 @@ pub mod token_2022_staking {
             ErrorCode::Unauthorized
         );
 
+        // Records already staked keep the unlock time they were created with
         config.min_stake_period = min_stake_period;
 
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            Clock::get()?.unix_timestamp
+        ));
+
         Ok(())
     }
 
     /**
-     *! 3. DEPOSIT TOKENS INTO CONTRACT
+     *! 2b. UPDATE REWARD RATE (Admin Only)
      */
+    pub fn update_reward_rate(ctx: Context<UpdateRewardRate>, reward_rate_bps: u16) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-    pub fn deposit_rewards(ctx: Context<DepositRewards>, amount: u64) -> Result<()> {
-        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
-            return Err(ErrorCode::TokenMintMismatch.into());
-        }
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        // Transfer tokens from the depositor to the contract's reward account
-        let cpi_accounts = TransferCheckedWithFee {
-            token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.depositor_ata.to_account_info(),
-            destination: ctx.accounts.config_ata.to_account_info(),
-            authority: ctx.accounts.depositor.to_account_info(),
-            mint: ctx.accounts.token_mint.to_account_info(),
-        };
+        // Existing records keep the rate they were staked at, only new stakes pick this up
+        let current_time = Clock::get()?.unix_timestamp;
+        config.reward_rate_bps = reward_rate_bps;
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        emit!(ConfigUpdated::new(config.key(), config, current_time));
 
-        let token_tax_percentage = ctx.accounts.config.tax_percentage as u64;
-        let token_decimals = ctx.accounts.config.decimals;
+        Ok(())
+    }
 
-        // -- Maximum Fee
+    /**
+     *! 2c. UPDATE EARLY UNSTAKE PENALTY (Admin Only)
+     */
+    pub fn update_early_unstake_penalty(
+        ctx: Context<UpdateEarlyUnstakePenalty>,
+        penalty_bps: u16,
+        linear_decay: bool,
+        destination: PenaltyDestination,
+        treasury: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let mint = &ctx.accounts.token_mint.to_account_info();
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        require!(
+            penalty_bps as u128 <= BPS_DENOMINATOR,
+            ErrorCode::InvalidPenalty
+        );
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+        config.early_unstake_penalty_bps = penalty_bps;
+        config.penalty_linear_decay = linear_decay;
+        config.penalty_destination = destination;
+        config.penalty_treasury = treasury;
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            Clock::get()?.unix_timestamp
+        ));
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+        Ok(())
+    }
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
-        };
+    /**
+     *! 2d. UPDATE UNSTAKE COOLDOWN (Admin Only)
+     */
+    pub fn update_unstake_cooldown(
+        ctx: Context<UpdateUnstakeCooldown>,
+        unstake_cooldown: i64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let fee = (amount * token_tax_percentage + 99) / 100;
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let mut final_fee = fee;
+        require!(unstake_cooldown >= 0, ErrorCode::InvalidCooldown);
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
-        }
+        // Only affects new requests, pending withdrawals keep their unlock time
+        config.unstake_cooldown = unstake_cooldown;
 
-        transfer_checked_with_fee(cpi_ctx, amount, token_decimals, final_fee)?;
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            Clock::get()?.unix_timestamp
+        ));
 
         Ok(())
     }
 
     /**
-     *! 4. WITHDRAW TOKENS FROM CONTRACT (Admin Only)
+     *! 2e. UPDATE APY BASIS (Admin Only)
      */
-
-    pub fn withdraw(ctx: Context<Withdraw>, config_pda_bump: u8) -> Result<()> {
+    pub fn update_apy_on_ui_amount(
+        ctx: Context<UpdateApyOnUiAmount>,
+        apy_on_ui_amount: bool,
+    ) -> Result<()> {
         let config = &mut ctx.accounts.config;
 
         require!(
@@ -132,460 +202,1893 @@ pub mod token_2022_staking {
             ErrorCode::Unauthorized
         );
 
-        if config.token_mint_address != ctx.accounts.token_mint.key() {
-            return Err(ErrorCode::TokenMintMismatch.into());
-        }
+        // Like a reward rate change, this only applies to records staked from now on
+        let current_time = Clock::get()?.unix_timestamp;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        config.apy_on_ui_amount = apy_on_ui_amount;
 
-        // Transfer Tokens from contract account to user account
-        let cpi_accounts = TransferCheckedWithFee {
-            token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.config_ata.to_account_info(),
-            destination: ctx.accounts.authority_ata.to_account_info(),
-            authority: config.to_account_info(),
-            mint: ctx.accounts.token_mint.to_account_info(),
-        };
+        emit!(ConfigUpdated::new(config.key(), config, current_time));
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
+        Ok(())
+    }
 
-        let seeds: &[&[&[u8]]] = &[&[CONFIG_PDA_SEED, &[config_pda_bump]]];
+    /**
+     *! 2f. PROPOSE NEW AUTHORITY (Admin Only)
+     */
+    pub fn propose_authority(ctx: Context<ProposeAuthority>, new_authority: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds);
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let total_reward_balance = ctx.accounts.config_ata.amount;
+        // Nothing changes until the new key signs `accept_authority`, a new proposal replaces this one
+        config.pending_authority = Some(new_authority);
 
-        let token_tax_percentage = config.tax_percentage as u64;
-        let token_decimals = config.decimals;
+        emit!(AuthorityProposed {
+            config: config.key(),
+            authority: config.authority,
+            pending_authority: new_authority,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-        // -- Maximum Fee
+        Ok(())
+    }
 
-        let mint = &ctx.accounts.token_mint.to_account_info();
+    /**
+     *! 2g. ACCEPT AUTHORITY (Pending Authority Only)
+     */
+    pub fn accept_authority(ctx: Context<AcceptAuthority>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        require!(
+            config.pending_authority == Some(ctx.accounts.new_authority.key()),
+            ErrorCode::Unauthorized
+        );
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+        let previous_authority = config.authority;
+        config.authority = ctx.accounts.new_authority.key();
+        config.pending_authority = None;
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+        emit!(AuthorityTransferred {
+            config: config.key(),
+            previous_authority,
+            new_authority: config.authority,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+        Ok(())
+    }
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
-        };
+    /**
+     *! 2h. SET PAUSE FLAGS (Admin Only)
+     */
+    pub fn set_pause_flags(ctx: Context<UpdatePauseState>, paused: u8) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let fee = (total_reward_balance * token_tax_percentage + 99) / 100; // Adding 99 for correct rounding
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let mut final_fee = fee;
+        require!(paused & !PAUSE_ALL == 0, ErrorCode::InvalidPauseFlags);
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
-        }
+        config.paused = paused;
 
-        transfer_checked_with_fee(cpi_ctx, total_reward_balance, token_decimals, final_fee)?;
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            Clock::get()?.unix_timestamp
+        ));
 
         Ok(())
     }
 
     /**
-     *! 5a. STAKE
+     *! 2i. SET EMERGENCY MODE (Admin Only)
      */
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
-        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
-            return Err(ErrorCode::TokenMintMismatch.into());
-        }
-
-        let user_stake_account = &mut ctx.accounts.user_stake_account;
-
-        // Transfer Tokens from user account to contract account
-        let cpi_accounts = TransferCheckedWithFee {
-            token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.user_ata.to_account_info(),
-            destination: ctx.accounts.config_ata.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-            mint: ctx.accounts.token_mint.to_account_info(),
-        };
+    pub fn set_emergency_mode(ctx: Context<UpdatePauseState>, emergency: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let token_tax_percentage = ctx.accounts.config.tax_percentage as u64;
-        let token_decimals = ctx.accounts.config.decimals;
+        // No rewards are emitted while in emergency mode, not even once it is lifted
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.emergency = emergency;
 
-        // -- Maximum Fee
+        emit!(ConfigUpdated::new(config.key(), config, current_time));
 
-        let mint = &ctx.accounts.token_mint.to_account_info();
+        Ok(())
+    }
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    /**
+     *! 2j. SET LOCK TIERS (Admin Only)
+     */
+    pub fn set_lock_tiers(ctx: Context<UpdateLockTiers>, lock_tiers: Vec<LockTier>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+        require!(
+            lock_tiers.len() <= MAX_LOCK_TIERS,
+            ErrorCode::TooManyLockTiers
+        );
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+        for lock_tier in lock_tiers.iter() {
+            require!(
+                lock_tier.lock_period >= 0 && lock_tier.multiplier_bps > 0,
+                ErrorCode::InvalidLockTier
+            );
+        }
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
-        };
+        // Existing records keep the shares and unlock time they were created with
+        config.lock_tiers = lock_tiers;
 
-        let fee = (amount * token_tax_percentage + 99) / 100; // Adding 99 for correct rounding
+        emit!(ConfigUpdated::new(
+            config.key(),
+            config,
+            Clock::get()?.unix_timestamp
+        ));
 
-        let mut final_fee = fee;
+        Ok(())
+    }
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
+    /**
+     *! 3. DEPOSIT TOKENS INTO CONTRACT
+     */
+    pub fn deposit_rewards<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositRewards<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
         }
 
-        transfer_checked_with_fee(cpi_ctx, amount, token_decimals, final_fee)?;
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_DEPOSIT),
+            ErrorCode::Paused
+        );
 
-        // Store Record
-        user_stake_account.authority = ctx.accounts.user.key();
+        // Transfer tokens from the depositor to the reward vault
+        let fee = transfer_to_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.depositor_ata.to_account_info(),
+            &ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.depositor.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            amount,
+            ctx.remaining_accounts,
+        )?;
 
-        let final_stake_value = amount - final_fee;
+        // Only what actually landed in the vault can be emitted
+        let received = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
 
-        user_stake_account.stakes.push(StakeRecord {
-            amount: final_stake_value,
-            timestamp: Clock::get()?.unix_timestamp,
+        let current_time = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        config.reward_budget = config
+            .reward_budget
+            .checked_add(received)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(RewardsDeposited {
+            config: config.key(),
+            depositor: ctx.accounts.depositor.key(),
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            fee,
+            reward_budget: config.reward_budget,
+            timestamp: current_time,
         });
 
         Ok(())
     }
 
     /**
-     *! 5b. STAKE
+     *! 4. WITHDRAW SURPLUS REWARDS FROM CONTRACT (Admin Only)
      */
-    pub fn stake_reallocx(ctx: Context<StakeRealloc>, amount: u64) -> Result<()> {
-        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
-            return Err(ErrorCode::TokenMintMismatch.into());
-        }
-
-        let user_stake_account = &mut ctx.accounts.user_stake_account;
-
-        // Transfer Tokens from user account to contract account
-        let cpi_accounts = TransferCheckedWithFee {
-            token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.user_ata.to_account_info(),
-            destination: ctx.accounts.config_ata.to_account_info(),
-            authority: ctx.accounts.user.to_account_info(),
-            mint: ctx.accounts.token_mint.to_account_info(),
-        };
+    pub fn withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, Withdraw<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let token_tax_percentage = ctx.accounts.config.tax_percentage as u64;
-        let token_decimals = ctx.accounts.config.decimals;
+        if config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
 
-        // -- Maximum Fee
+        // Bring liabilities up to date, anything in the reward vault not owed to stakers, as
+        // rewards or compounded principal, is surplus
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let surplus = ctx
+            .accounts
+            .reward_vault
+            .amount
+            .saturating_sub(config.reward_liabilities)
+            .saturating_sub(config.compounded_principal);
+
+        require!(amount <= surplus, ErrorCode::InsufficientSurplus);
+
+        // What stays behind beyond the liabilities can still be emitted
+        config.reward_budget = config.reward_budget.min(surplus - amount);
+
+        let fee = transfer_from_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.authority_ata.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            &ctx.accounts.config,
+            amount,
+            ctx.remaining_accounts,
+        )?;
+
+        emit!(Withdrawn {
+            config: ctx.accounts.config.key(),
+            authority: ctx.accounts.authority.key(),
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            fee,
+            reward_budget: ctx.accounts.config.reward_budget,
+            timestamp: current_time,
+        });
 
-        let mint = &ctx.accounts.token_mint.to_account_info();
+        Ok(())
+    }
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    /**
+     *! 4b. ENABLE WIND-DOWN (Admin Only)
+     */
+    pub fn enable_wind_down(ctx: Context<EnableWindDown>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+        // Emissions stop here, stakers keep what they accrued and exit as usual
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.wind_down = true;
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+        emit!(ConfigUpdated::new(config.key(), config, current_time));
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
-        };
+        Ok(())
+    }
 
-        let fee = (amount * token_tax_percentage + 99) / 100; // Adding 99 for correct rounding
+    /**
+     *! 4c. WITHDRAW REMAINING FUNDS AFTER WIND-DOWN (Admin Only)
+     */
+    pub fn withdraw_remaining<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawRemaining<'info>>,
+    ) -> Result<()> {
+        let config = &ctx.accounts.config;
 
-        let mut final_fee = fee;
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
+        if config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
         }
 
-        transfer_checked_with_fee(cpi_ctx, amount, token_decimals, final_fee)?;
+        require!(config.wind_down, ErrorCode::NotWindingDown);
+        require!(
+            config.total_staked == 0
+                && config.total_shares == 0
+                && config.total_pending_withdrawals == 0,
+            ErrorCode::StakersRemaining
+        );
 
-        // Store Record
+        let mut amount: u64 = 0;
+        let mut fee: u64 = 0;
+
+        // Every staker has exited, whatever is left in either vault is rounding dust or surplus.
+        // With no record left no rewards are owed either, rewards kept from relocking are paid
+        // with the last record, so `reward_liabilities` only holds what `update_pool` charged
+        // rounded up and never paid out
+        for vault in [&ctx.accounts.stake_vault, &ctx.accounts.reward_vault] {
+            let vault_amount = vault.amount;
+            if vault_amount > 0 {
+                let vault_fee = transfer_from_vault(
+                    &ctx.accounts.token_program.to_account_info(),
+                    &vault.to_account_info(),
+                    &ctx.accounts.authority_ata.to_account_info(),
+                    &ctx.accounts.token_mint.to_account_info(),
+                    config,
+                    vault_amount,
+                    ctx.remaining_accounts,
+                )?;
+
+                amount = amount
+                    .checked_add(vault_amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+                fee = fee.checked_add(vault_fee).ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
 
-        let final_stake_value = amount - final_fee;
+        let config = &mut ctx.accounts.config;
+        config.reward_budget = 0;
+        config.reward_liabilities = 0;
 
-        user_stake_account.stakes.push(StakeRecord {
-            amount: final_stake_value,
-            timestamp: Clock::get()?.unix_timestamp,
+        let current_time = Clock::get()?.unix_timestamp;
+        emit!(Withdrawn {
+            config: config.key(),
+            authority: ctx.accounts.authority.key(),
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            fee,
+            reward_budget: 0,
+            timestamp: current_time,
         });
 
         Ok(())
     }
 
     /**
-     *! 6. UNSTAKE
+     *! 4d. HARVEST WITHHELD TRANSFER FEES TO THE MINT
      */
-
-    pub fn unstake(ctx: Context<Unstake>, config_pda_bump: u8) -> Result<()> {
+    pub fn harvest_withheld_fees<'info>(
+        ctx: Context<'_, '_, '_, 'info, HarvestWithheldFees<'info>>,
+    ) -> Result<()> {
         if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
             return Err(ErrorCode::TokenMintMismatch.into());
         }
 
-        let user_stake_account = &mut ctx.accounts.user_stake_account;
-        let config = &mut ctx.accounts.config;
-
-        let current_time = Clock::get()?.unix_timestamp;
-        let annual_rate = 0.15; // APY of 15%
-        let seconds_in_year = 365.0 * 24.0 * 3600.0;
-        let second_rate = annual_rate / seconds_in_year; // Rate per second
-        let min_stake_period_seconds = config.min_stake_period * 86_400;
-
-        let mut total_reward = 0.0;
-        let mut total_staked_amount = 0;
-
-        for stake in user_stake_account.stakes.iter_mut() {
-            let duration = current_time - stake.timestamp;
-            if duration >= min_stake_period_seconds {
-                let reward_duration = duration as f64; // Duration in seconds
-                total_reward += stake.amount as f64 * second_rate * reward_duration;
-                total_staked_amount += stake.amount;
-            }
-        }
-
-        // Convert total_reward to u64
-        let total_reward_u64 = total_reward as u64;
-
-        let current_rewards_balance = ctx.accounts.config_ata.amount;
-
-        // Ensure there are enough rewards in the contract
-        if current_rewards_balance < total_reward_u64 {
-            return Err(ErrorCode::InsufficientRewards.into());
-        }
+        require!(
+            has_transfer_fee_config(&ctx.accounts.token_mint.to_account_info())?,
+            ErrorCode::NoTransferFeeConfig
+        );
 
-        // Calculate the total amount to transfer (rewards + staked amount)
-        let total_amount_to_transfer = total_reward_u64 + total_staked_amount;
+        // Withheld fees never count towards a balance, so moving them leaves the pool untouched
+        let mut sources = vec![
+            ctx.accounts.stake_vault.to_account_info(),
+            ctx.accounts.reward_vault.to_account_info(),
+        ];
+        sources.extend_from_slice(ctx.remaining_accounts);
 
-        // Transfer Tokens from contract account to user account
-        let cpi_accounts = TransferCheckedWithFee {
+        let cpi_accounts = HarvestWithheldTokensToMint {
             token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.config_ata.to_account_info(),
-            destination: ctx.accounts.user_ata.to_account_info(),
-            authority: ctx.accounts.config.to_account_info(),
             mint: ctx.accounts.token_mint.to_account_info(),
         };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-
-        let seeds: &[&[&[u8]]] = &[&[CONFIG_PDA_SEED, &[config_pda_bump]]];
-
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds);
-
-        let token_tax_percentage = ctx.accounts.config.tax_percentage as u64;
-        let token_decimals = ctx.accounts.config.decimals;
+        harvest_withheld_tokens_to_mint(cpi_ctx, sources.clone())?;
 
-        // -- Maximum Fee
-
-        let mint = &ctx.accounts.token_mint.to_account_info();
+        emit!(WithheldFeesHarvested {
+            config: ctx.accounts.config.key(),
+            source_count: sources.len() as u32,
+            timestamp: Clock::get()?.unix_timestamp,
+        });
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+        Ok(())
+    }
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+    /**
+     *! 4e. WITHDRAW WITHHELD TRANSFER FEES INTO REWARD VAULT (Admin Only)
+     */
+    pub fn withdraw_withheld_fees(ctx: Context<WithdrawWithheldFees>) -> Result<()> {
+        let config = &ctx.accounts.config;
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+        require!(
+            *ctx.accounts.authority.key == config.authority,
+            ErrorCode::Unauthorized
+        );
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+        if config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
+        let mint_info = ctx.accounts.token_mint.to_account_info();
+        let amount = {
+            let mint_data = mint_info.data.borrow();
+            let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+            let transfer_fee_config = state
+                .get_extension::<TransferFeeConfig>()
+                .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+
+            let withdraw_authority: Option<Pubkey> =
+                transfer_fee_config.withdraw_withheld_authority.into();
+            require!(
+                withdraw_authority == Some(config.key()),
+                ErrorCode::NotWithheldAuthority
+            );
+
+            u64::from(transfer_fee_config.withheld_amount)
         };
 
-        let fee = (total_amount_to_transfer * token_tax_percentage + 99) / 100;
-
-        let mut final_fee = fee;
+        let pool_id_bytes = config.pool_id.to_le_bytes();
+        let seeds: &[&[&[u8]]] = &[&[
+            CONFIG_PDA_SEED,
+            config.token_mint_address.as_ref(),
+            &pool_id_bytes,
+            &[config.bump],
+        ]];
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
-        }
+        let cpi_accounts = WithdrawWithheldTokensFromMint {
+            token_program_id: ctx.accounts.token_program.to_account_info(),
+            mint: mint_info,
+            destination: ctx.accounts.reward_vault.to_account_info(),
+            authority: config.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            cpi_accounts,
+            seeds,
+        );
 
-        transfer_checked_with_fee(cpi_ctx, total_amount_to_transfer, token_decimals, final_fee)?;
+        withdraw_withheld_tokens_from_mint(cpi_ctx)?;
 
-        // Remove all stakes
-        user_stake_account.stakes.clear();
+        // Withdrawing withheld fees charges no fee, all of it becomes emittable rewards
+        let current_time = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        config.reward_budget = config
+            .reward_budget
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        emit!(WithheldFeesWithdrawn {
+            config: config.key(),
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            reward_budget: config.reward_budget,
+            timestamp: current_time,
+        });
 
         Ok(())
     }
 
     /**
-     *! 7. CLAIM REWARDS
+     *! 5. STAKE
      */
-
-    pub fn claim_rewards(ctx: Context<ClaimRewards>, config_pda_bump: u8) -> Result<()> {
+    pub fn stake<'info>(
+        ctx: Context<'_, '_, '_, 'info, Stake<'info>>,
+        amount: u64,
+        tier: Option<u8>,
+    ) -> Result<()> {
         if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
             return Err(ErrorCode::TokenMintMismatch.into());
         }
 
-        let user_stake_account = &mut ctx.accounts.user_stake_account;
-        let config = &mut ctx.accounts.config;
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_STAKE),
+            ErrorCode::Paused
+        );
 
-        let current_time = Clock::get()?.unix_timestamp;
-        let annual_rate = 0.15; // APY of 15%
-        let seconds_in_year = 365.0 * 24.0 * 3600.0;
-        let second_rate = annual_rate / seconds_in_year; // Rate per second
-        let min_stake_period_seconds = config.min_stake_period * 86_400;
-
-        let mut total_reward = 0.0;
-
-        for stake in user_stake_account.stakes.iter_mut() {
-            let duration = current_time - stake.timestamp;
-
-            msg!("Current Time: {}", current_time);
-            msg!("Stake Timestamp: {}", stake.timestamp);
-            msg!("Duration: {}", duration);
-            msg!("Min Stake Period: {}", min_stake_period_seconds);
-            msg!("Validation: {}", duration >= config.min_stake_period);
-
-            if duration >= min_stake_period_seconds {
-                let reward_duration = duration as f64; // Duration in seconds
-                total_reward += stake.amount as f64 * second_rate * reward_duration;
-                stake.timestamp = current_time;
-            }
-        }
+        require!(!ctx.accounts.config.emergency, ErrorCode::EmergencyMode);
 
-        // Convert total_reward to u64
-        let total_reward_u64 = total_reward as u64;
+        require!(!ctx.accounts.config.wind_down, ErrorCode::PoolWindingDown);
 
-        let current_rewards_balance = ctx.accounts.config_ata.amount;
+        // Transfer Tokens from user account to the stake vault
+        let fee = transfer_to_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.user_ata.to_account_info(),
+            &ctx.accounts.stake_vault.to_account_info(),
+            &ctx.accounts.user.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            amount,
+            ctx.remaining_accounts,
+        )?;
 
-        msg!("Current Reward Balance: {}", current_rewards_balance);
-        msg!("Total Rewards u64: {}", total_reward_u64);
-        // Ensure there are enough rewards in the contract
-        if current_rewards_balance < total_reward_u64 {
-            return Err(ErrorCode::InsufficientRewards.into());
-        }
+        let lock_tier = ctx.accounts.config.lock_tier(tier)?;
 
-        // Transfer Tokens from contract account to user account
-        let cpi_accounts = TransferCheckedWithFee {
-            token_program_id: ctx.accounts.token_program.to_account_info(),
-            source: ctx.accounts.config_ata.to_account_info(),
-            destination: ctx.accounts.user_ata.to_account_info(),
-            authority: ctx.accounts.config.to_account_info(),
-            mint: ctx.accounts.token_mint.to_account_info(),
-        };
+        let final_stake_value = amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+        require!(final_stake_value > 0, ErrorCode::ZeroStakeAmount);
 
-        let cpi_program = ctx.accounts.token_program.to_account_info();
+        // Store Record
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let current_time = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let stake = user_stake_account.add_stake(
+            final_stake_value,
+            tier,
+            &lock_tier,
+            config.emission_rate_bps(),
+            current_time,
+            config.acc_reward_per_share,
+        )?;
+
+        config.add_staked(stake.amount, stake.shares)?;
+
+        emit!(Staked {
+            config: config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: stake.id,
+            tier,
+            reward_rate_bps: stake.reward_rate_bps,
+            shares: stake.shares,
+            unlock_time: stake.unlock_time,
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            fee,
+            user_staked_amount: user_stake_account.staked_amount,
+            total_staked: config.total_staked,
+            timestamp: current_time,
+        });
 
-        let seeds: &[&[&[u8]]] = &[&[CONFIG_PDA_SEED, &[config_pda_bump]]];
+        Ok(())
+    }
 
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds);
+    /**
+     *! 5a. OPEN STAKE ACCOUNT
+     */
+    pub fn open_stake_account(ctx: Context<OpenStakeAccount>) -> Result<()> {
+        // Starts empty, `stake` grows it by one record at a time
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        user_stake_account.authority = ctx.accounts.user.key();
+        user_stake_account.bump = ctx.bumps.user_stake_account;
 
-        let token_tax_percentage = ctx.accounts.config.tax_percentage as u64;
-        let token_decimals = ctx.accounts.config.decimals;
+        emit!(StakeAccountOpened {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 5b. EXTEND STAKE
+     */
+    pub fn extend_stake(ctx: Context<ExtendStake>, stake_id: u64, tier: Option<u8>) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_STAKE),
+            ErrorCode::Paused
+        );
+
+        require!(!ctx.accounts.config.emergency, ErrorCode::EmergencyMode);
+
+        require!(!ctx.accounts.config.wind_down, ErrorCode::PoolWindingDown);
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let lock_tier = config.lock_tier(tier)?;
+        let index = user_stake_account.find_stake(stake_id)?;
+
+        // No tokens move, the record is relocked in place and what it earned so far is kept for
+        // the next claim
+        let (previous, stake, settled_reward) = user_stake_account.relock_stake(
+            index,
+            tier,
+            &lock_tier,
+            config.emission_rate_bps(),
+            current_time,
+            config.acc_reward_per_share,
+        )?;
+
+        config.remove_staked(previous.amount, previous.shares)?;
+        config.add_staked(stake.amount, stake.shares)?;
+
+        emit!(StakeExtended {
+            config: config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id,
+            tier,
+            reward_rate_bps: stake.reward_rate_bps,
+            shares: stake.shares,
+            previous_unlock_time: previous.unlock_time,
+            unlock_time: stake.unlock_time,
+            settled_reward,
+            unclaimed_rewards: user_stake_account.unclaimed_rewards,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6. UNSTAKE
+     */
+    pub fn unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unstake<'info>>,
+        close_if_empty: bool,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        require!(
+            ctx.accounts.config.unstake_cooldown == 0,
+            ErrorCode::CooldownRequired
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        let total_reward_u64 = user_stake_account.pending_reward(config.acc_reward_per_share)?;
+        config.release_liability(total_reward_u64)?;
+
+        // Every matured record is paid out, immature ones stay staked
+        let total_staked_amount = user_stake_account.matured_amount(current_time)?;
+        let shares = user_stake_account.remove_matured(
+            total_staked_amount,
+            UnstakeOrder::Fifo,
+            current_time,
+        )?;
+        user_stake_account.settle(config.acc_reward_per_share)?;
+
+        config.remove_staked(total_staked_amount, shares)?;
+
+        let fee = ctx.accounts.pay_out(
+            total_staked_amount,
+            total_reward_u64,
+            ctx.remaining_accounts,
+        )?;
+
+        emit!(Unstaked {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: None,
+            principal: total_staked_amount,
+            principal_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                total_staked_amount,
+                current_time
+            )?,
+            reward: total_reward_u64,
+            reward_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                total_reward_u64,
+                current_time
+            )?,
+            penalty: 0,
+            withdrawal_id: None,
+            fee,
+            user_staked_amount: ctx.accounts.user_stake_account.staked_amount,
+            total_staked: ctx.accounts.config.total_staked,
+            timestamp: current_time,
+        });
+
+        // Immature records or pending withdrawals keep the account open
+        if close_if_empty && ctx.accounts.user_stake_account.is_empty() {
+            ctx.accounts
+                .user_stake_account
+                .close(ctx.accounts.user.to_account_info())?;
+
+            emit!(StakeAccountClosed {
+                config: ctx.accounts.config.key(),
+                user: ctx.accounts.user.key(),
+                timestamp: current_time,
+            });
+        }
+
+        Ok(())
+    }
+
+    /**
+     *! 6b. UNSTAKE AMOUNT
+     */
+    pub fn unstake_amount<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unstake<'info>>,
+        amount: u64,
+        order: UnstakeOrder,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        require!(
+            ctx.accounts.config.unstake_cooldown == 0,
+            ErrorCode::CooldownRequired
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        let total_reward_u64 = user_stake_account.pending_reward(config.acc_reward_per_share)?;
+        config.release_liability(total_reward_u64)?;
+
+        // Take exactly `amount` out of the matured records, the rest stays staked
+        let shares = user_stake_account.remove_matured(amount, order, current_time)?;
+        user_stake_account.settle(config.acc_reward_per_share)?;
+
+        config.remove_staked(amount, shares)?;
+
+        let fee = ctx
+            .accounts
+            .pay_out(amount, total_reward_u64, ctx.remaining_accounts)?;
+
+        emit!(Unstaked {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: None,
+            principal: amount,
+            principal_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            reward: total_reward_u64,
+            reward_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                total_reward_u64,
+                current_time
+            )?,
+            penalty: 0,
+            withdrawal_id: None,
+            fee,
+            user_staked_amount: ctx.accounts.user_stake_account.staked_amount,
+            total_staked: ctx.accounts.config.total_staked,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6c. UNSTAKE SINGLE RECORD
+     */
+    pub fn unstake_record<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unstake<'info>>,
+        stake_id: u64,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        require!(
+            ctx.accounts.config.unstake_cooldown == 0,
+            ErrorCode::CooldownRequired
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let index = user_stake_account.find_stake(stake_id)?;
+        require!(
+            user_stake_account.stakes[index].is_matured(current_time),
+            ErrorCode::StakeNotMatured
+        );
+
+        // Only this record's principal and reward are paid, the others keep accruing untouched
+        let (stake, reward) =
+            user_stake_account.remove_stake(index, config.acc_reward_per_share)?;
+        let reward = reward
+            .checked_add(user_stake_account.take_unclaimed_if_last())
+            .ok_or(ErrorCode::MathOverflow)?;
+        config.release_liability(reward)?;
+
+        let principal = stake.amount;
+        config.remove_staked(principal, stake.shares)?;
+
+        let fee = ctx
+            .accounts
+            .pay_out(principal, reward, ctx.remaining_accounts)?;
+
+        emit!(Unstaked {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: Some(stake_id),
+            principal,
+            principal_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                principal,
+                current_time
+            )?,
+            reward,
+            reward_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                reward,
+                current_time
+            )?,
+            penalty: 0,
+            withdrawal_id: None,
+            fee,
+            user_staked_amount: ctx.accounts.user_stake_account.staked_amount,
+            total_staked: ctx.accounts.config.total_staked,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6d. EARLY UNSTAKE WITH PENALTY
+     */
+    pub fn early_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, EarlyUnstake<'info>>,
+        stake_id: u64,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        // Locks are only breakable once the admin has priced it
+        require!(
+            ctx.accounts.config.early_unstake_penalty_bps > 0,
+            ErrorCode::EarlyUnstakeDisabled
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let index = user_stake_account.find_stake(stake_id)?;
+        let remaining_lock = user_stake_account.stakes[index].remaining_lock(current_time);
+
+        // Rewards already earned are kept, breaking the lock only costs the principal penalty
+        let (stake, reward) =
+            user_stake_account.remove_stake(index, config.acc_reward_per_share)?;
+        config.release_liability(reward)?;
+
+        let unclaimed_rewards = user_stake_account.take_unclaimed_if_last();
+        config.release_liability(unclaimed_rewards)?;
+        let reward = reward
+            .checked_add(unclaimed_rewards)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let principal = stake.amount;
+        config.remove_staked(principal, stake.shares)?;
+
+        let penalty =
+            config.early_unstake_penalty(principal, remaining_lock, stake.lock_duration())?;
+
+        // Penalties routed to the reward vault become emittable rewards
+        let penalty_to_rewards = ctx
+            .accounts
+            .route_penalty(penalty, ctx.remaining_accounts)?;
+        let config = &mut ctx.accounts.config;
+        config.reward_budget = config
+            .reward_budget
+            .checked_add(penalty_to_rewards)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let payout = principal
+            .checked_sub(penalty)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        // Breaking the lock does not skip the cooldown, what is left after the penalty is queued
+        // like any other exit
+        let withdrawal_id = if config.unstake_cooldown > 0 {
+            let unlock_time = current_time
+                .checked_add(config.unstake_cooldown)
+                .ok_or(ErrorCode::MathOverflow)?;
+            let withdrawal_id = ctx
+                .accounts
+                .user_stake_account
+                .add_pending_withdrawal(payout, unlock_time)?;
+
+            config.total_pending_withdrawals = config
+                .total_pending_withdrawals
+                .checked_add(payout)
+                .ok_or(ErrorCode::MathOverflow)?;
+            Some(withdrawal_id)
+        } else {
+            None
+        };
+
+        let fee = ctx.accounts.pay_out(
+            if withdrawal_id.is_some() { 0 } else { payout },
+            reward,
+            ctx.remaining_accounts,
+        )?;
+
+        emit!(Unstaked {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: Some(stake_id),
+            principal,
+            principal_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                principal,
+                current_time
+            )?,
+            reward,
+            reward_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                reward,
+                current_time
+            )?,
+            penalty,
+            withdrawal_id,
+            fee,
+            user_staked_amount: ctx.accounts.user_stake_account.staked_amount,
+            total_staked: ctx.accounts.config.total_staked,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6e. REQUEST UNSTAKE (Start Cooldown)
+     */
+    pub fn request_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, RequestUnstake<'info>>,
+        amount: u64,
+        order: UnstakeOrder,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        require!(amount > 0, ErrorCode::ZeroAmount);
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        let total_reward_u64 = user_stake_account.pending_reward(config.acc_reward_per_share)?;
+        config.release_liability(total_reward_u64)?;
+
+        // The requested principal stops earning now and waits out the cooldown in the stake vault
+        let shares = user_stake_account.remove_matured(amount, order, current_time)?;
+        user_stake_account.settle(config.acc_reward_per_share)?;
+
+        let unlock_time = current_time
+            .checked_add(config.unstake_cooldown)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let withdrawal_id = user_stake_account.add_pending_withdrawal(amount, unlock_time)?;
+
+        config.remove_staked(amount, shares)?;
+        config.total_pending_withdrawals = config
+            .total_pending_withdrawals
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let current_rewards_balance = ctx.accounts.reward_vault.amount;
+
+        // Ensure there are enough rewards in the contract
+        if current_rewards_balance < total_reward_u64 {
+            return Err(ErrorCode::InsufficientRewards.into());
+        }
+
+        let fee = if total_reward_u64 > 0 {
+            transfer_from_vault(
+                &ctx.accounts.token_program.to_account_info(),
+                &ctx.accounts.reward_vault.to_account_info(),
+                &ctx.accounts.user_ata.to_account_info(),
+                &ctx.accounts.token_mint.to_account_info(),
+                &ctx.accounts.config,
+                total_reward_u64,
+                ctx.remaining_accounts,
+            )?
+        } else {
+            0
+        };
+
+        emit!(UnstakeRequested {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            withdrawal_id,
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            unlock_time,
+            reward: total_reward_u64,
+            reward_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                total_reward_u64,
+                current_time
+            )?,
+            fee,
+            user_staked_amount: ctx.accounts.user_stake_account.staked_amount,
+            total_staked: ctx.accounts.config.total_staked,
+            total_pending_withdrawals: ctx.accounts.config.total_pending_withdrawals,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6f. COMPLETE UNSTAKE (After Cooldown)
+     */
+    pub fn complete_unstake<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unstake<'info>>,
+        withdrawal_id: u64,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_UNSTAKE),
+            ErrorCode::Paused
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let index = user_stake_account.find_pending_withdrawal(withdrawal_id)?;
+        require!(
+            current_time >= user_stake_account.pending_withdrawals[index].unlock_time,
+            ErrorCode::CooldownNotElapsed
+        );
+
+        let withdrawal = user_stake_account.pending_withdrawals.remove(index);
+
+        config.total_pending_withdrawals = config
+            .total_pending_withdrawals
+            .checked_sub(withdrawal.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let fee = ctx
+            .accounts
+            .pay_out(withdrawal.amount, 0, ctx.remaining_accounts)?;
+
+        emit!(UnstakeCompleted {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            withdrawal_id,
+            amount: withdrawal.amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                withdrawal.amount,
+                current_time
+            )?,
+            fee,
+            total_pending_withdrawals: ctx.accounts.config.total_pending_withdrawals,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6g. CANCEL UNSTAKE (Re-stake Pending Withdrawal)
+     */
+    pub fn cancel_unstake(ctx: Context<CancelUnstake>, withdrawal_id: u64) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_STAKE),
+            ErrorCode::Paused
+        );
+
+        require!(!ctx.accounts.config.emergency, ErrorCode::EmergencyMode);
+
+        require!(!ctx.accounts.config.wind_down, ErrorCode::PoolWindingDown);
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        let index = user_stake_account.find_pending_withdrawal(withdrawal_id)?;
+        let withdrawal = user_stake_account.pending_withdrawals.remove(index);
+
+        // The tokens never left the stake vault, they come back as a fresh base tier record
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        let lock_tier = config.lock_tier(None)?;
+        let stake = user_stake_account.add_stake(
+            withdrawal.amount,
+            None,
+            &lock_tier,
+            config.emission_rate_bps(),
+            current_time,
+            config.acc_reward_per_share,
+        )?;
+
+        config.total_pending_withdrawals = config
+            .total_pending_withdrawals
+            .checked_sub(withdrawal.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        config.add_staked(stake.amount, stake.shares)?;
+
+        emit!(UnstakeCancelled {
+            config: config.key(),
+            user: ctx.accounts.user.key(),
+            withdrawal_id,
+            stake_id: stake.id,
+            amount: withdrawal.amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                withdrawal.amount,
+                current_time
+            )?,
+            user_staked_amount: user_stake_account.staked_amount,
+            total_staked: config.total_staked,
+            total_pending_withdrawals: config.total_pending_withdrawals,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6h. CLOSE STAKE ACCOUNT
+     */
+    pub fn close_stake_account(ctx: Context<CloseStakeAccount>) -> Result<()> {
+        // Rent goes back to the user through the `close` constraint
+        require!(
+            ctx.accounts.user_stake_account.is_empty(),
+            ErrorCode::StakeAccountNotEmpty
+        );
+
+        emit!(StakeAccountClosed {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            timestamp: Clock::get()?.unix_timestamp,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 6i. EMERGENCY WITHDRAW
+     */
+    pub fn emergency_withdraw<'info>(
+        ctx: Context<'_, '_, '_, 'info, Unstake<'info>>,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        // Works whatever the pause flags, lock or cooldown say
+        require!(ctx.accounts.config.emergency, ErrorCode::NotEmergencyMode);
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        // Unpaid rewards are forfeited and go back to the budget for everyone else
+        let forfeited_reward = user_stake_account.pending_reward(config.acc_reward_per_share)?;
+        config.release_liability(forfeited_reward)?;
+        config.reward_budget = config
+            .reward_budget
+            .checked_add(forfeited_reward)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let shares = user_stake_account.shares;
+        let (staked, pending_withdrawals) = user_stake_account.remove_all()?;
+
+        config.remove_staked(staked, shares)?;
+        config.total_pending_withdrawals = config
+            .total_pending_withdrawals
+            .checked_sub(pending_withdrawals)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let principal = staked
+            .checked_add(pending_withdrawals)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let fee = ctx.accounts.pay_out(principal, 0, ctx.remaining_accounts)?;
+
+        emit!(EmergencyWithdrawn {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            principal,
+            principal_ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                principal,
+                current_time
+            )?,
+            forfeited_reward,
+            fee,
+            total_staked: ctx.accounts.config.total_staked,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 7. CLAIM REWARDS
+     */
+    pub fn claim_rewards<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimRewards<'info>>,
+    ) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_CLAIM),
+            ErrorCode::Paused
+        );
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        // Rewards vest continuously, the lock only applies to principal
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+        let total_reward_u64 = user_stake_account.pending_reward(config.acc_reward_per_share)?;
+        user_stake_account.settle(config.acc_reward_per_share)?;
+        config.release_liability(total_reward_u64)?;
+
+        let current_rewards_balance = ctx.accounts.reward_vault.amount;
+
+        // Ensure there are enough rewards in the contract
+        if current_rewards_balance < total_reward_u64 {
+            return Err(ErrorCode::InsufficientRewards.into());
+        }
+
+        // Transfer Tokens from the reward vault to user account
+        let fee = transfer_from_vault(
+            &ctx.accounts.token_program.to_account_info(),
+            &ctx.accounts.reward_vault.to_account_info(),
+            &ctx.accounts.user_ata.to_account_info(),
+            &ctx.accounts.token_mint.to_account_info(),
+            &ctx.accounts.config,
+            total_reward_u64,
+            ctx.remaining_accounts,
+        )?;
+
+        emit!(RewardsClaimed {
+            config: ctx.accounts.config.key(),
+            user: ctx.accounts.user.key(),
+            amount: total_reward_u64,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                total_reward_u64,
+                current_time
+            )?,
+            fee,
+            reward_liabilities: ctx.accounts.config.reward_liabilities,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+
+    /**
+     *! 7b. COMPOUND REWARDS
+     */
+    pub fn compound(ctx: Context<Compound>, tier: Option<u8>) -> Result<()> {
+        if ctx.accounts.config.token_mint_address != ctx.accounts.token_mint.key() {
+            return Err(ErrorCode::TokenMintMismatch.into());
+        }
+
+        require!(
+            !ctx.accounts.config.is_paused(PAUSE_CLAIM | PAUSE_STAKE),
+            ErrorCode::Paused
+        );
+
+        require!(!ctx.accounts.config.emergency, ErrorCode::EmergencyMode);
+
+        require!(!ctx.accounts.config.wind_down, ErrorCode::PoolWindingDown);
+
+        let user_stake_account = &mut ctx.accounts.user_stake_account;
+        let config = &mut ctx.accounts.config;
+
+        let current_time = Clock::get()?.unix_timestamp;
+        config.update_pool(current_time)?;
+        config.sync_interest_rate(&ctx.accounts.token_mint.to_account_info())?;
+
+        let lock_tier = config.lock_tier(tier)?;
+
+        let stake = config.compound(user_stake_account, tier, &lock_tier, current_time)?;
+        let amount = stake.amount;
+
+        emit!(Compounded {
+            config: config.key(),
+            user: ctx.accounts.user.key(),
+            stake_id: stake.id,
+            tier,
+            reward_rate_bps: stake.reward_rate_bps,
+            shares: stake.shares,
+            unlock_time: stake.unlock_time,
+            amount,
+            ui_amount: ui_amount(
+                &ctx.accounts.token_mint.to_account_info(),
+                amount,
+                current_time
+            )?,
+            user_staked_amount: user_stake_account.staked_amount,
+            total_staked: config.total_staked,
+            timestamp: current_time,
+        });
+
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------------------------
+//                                  Instruction Contexts
+// ----------------------------------------------------------------------------------------------
+
+#[derive(Accounts)]
+#[instruction(pool_id: u64)]
+pub struct Initialize<'info> {
+    #[account(
+        init,
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &pool_id.to_le_bytes()],
+        bump,
+        payer = authority,
+        space = Config::LEN
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump,
+        payer = authority,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateMinStakePeriod<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateRewardRate<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateEarlyUnstakePenalty<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateUnstakeCooldown<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateApyOnUiAmount<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAuthority<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAuthority<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub new_authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdatePauseState<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateLockTiers<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct DepositRewards<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = depositor,
+        token::token_program = token_program,
+    )]
+    pub depositor_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub depositor: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Withdraw<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = authority,
+        token::token_program = token_program,
+    )]
+    pub authority_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct EnableWindDown<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawRemaining<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump = config.stake_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = authority,
+        token::token_program = token_program,
+    )]
+    pub authority_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
-        // -- Maximum Fee
+#[derive(Accounts)]
+pub struct HarvestWithheldFees<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
 
-        let mint = &ctx.accounts.token_mint.to_account_info();
+    #[account(
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump = config.stake_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
 
-        // Load the TransferFeeConfig extension data
-        let mint_data = mint.data.borrow();
-        let state_with_extensions =
-            StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
 
-        let transfer_fee_config = state_with_extensions
-            .get_extension::<TransferFeeConfig>()
-            .map_err(|_| ErrorCode::NoTransferFeeConfig)?;
+    #[account(
+        mut,
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
-        let older_maximum_fee: u64 = transfer_fee_config.older_transfer_fee.maximum_fee.into();
-        // let older_epoch: u64 = transfer_fee_config.older_transfer_fee.epoch.into();
-        let newer_maximum_fee: u64 = transfer_fee_config.newer_transfer_fee.maximum_fee.into();
-        let newer_epoch: u64 = transfer_fee_config.newer_transfer_fee.epoch.into();
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
-        // Get the current epoch from the Clock sysvar
-        let clock = Clock::get()?;
-        let current_epoch = clock.epoch;
+#[derive(Accounts)]
+pub struct WithdrawWithheldFees<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
 
-        let maximum_fee = if current_epoch < newer_epoch {
-            older_maximum_fee
-        } else {
-            newer_maximum_fee
-        };
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
 
-        let fee = (total_reward_u64 * token_tax_percentage + 99) / 100;
+    #[account(
+        mut,
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
-        let mut final_fee = fee;
+    pub authority: Signer<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
-        if fee > maximum_fee {
-            final_fee = maximum_fee;
-        }
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
 
-        transfer_checked_with_fee(cpi_ctx, total_reward_u64, token_decimals, final_fee)?;
+    #[account(
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump = config.stake_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
 
-        Ok(())
-    }
-}
+    // Opened by `open_stake_account`, grown by exactly one record here
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut,
+        realloc = UserStakeAccount::space(
+            user_stake_account.stakes.len() + 1,
+            user_stake_account.pending_withdrawals.len()
+        ),
+        realloc::payer = user,
+        realloc::zero = false,
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
 
-// ----------------------------------------------------------------------------------------------
-//                                  Instruction Contexts
-// ----------------------------------------------------------------------------------------------
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
 
 #[derive(Accounts)]
-pub struct Initialize<'info> {
+pub struct OpenStakeAccount<'info> {
     #[account(
-        init,
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
-        payer = authority,
-        space = Config::LEN
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
     )]
     pub config: Account<'info, Config>,
 
     #[account(
         init,
-        seeds = [CONFIG_ATA_SEED.as_ref()],
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
         bump,
-        payer = authority,
+        payer = user,
+        space = UserStakeAccount::LEN
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendStake<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    pub user: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump = config.stake_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
         token::mint = token_mint,
         token::authority = config,
         token::token_program = token_program,
     )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mint::token_program = token_program
@@ -593,85 +2096,363 @@ pub struct Initialize<'info> {
     pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub user: Signer<'info>,
+
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> Unstake<'info> {
+    /// Send unstaked principal and rewards to the user, see `transfer_payout`.
+    pub fn pay_out(
+        &mut self,
+        principal: u64,
+        reward: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        transfer_payout(
+            &self.token_program.to_account_info(),
+            &self.stake_vault.to_account_info(),
+            &self.reward_vault,
+            &self.user_ata.to_account_info(),
+            &self.token_mint.to_account_info(),
+            &mut self.config,
+            principal,
+            reward,
+            remaining_accounts,
+        )
+    }
 }
 
 #[derive(Accounts)]
-pub struct UpdateMinStakePeriod<'info> {
+pub struct RequestUnstake<'info> {
     #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
         mut
     )]
     pub config: Account<'info, Config>,
-    pub authority: Signer<'info>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // Room for one more pending withdrawal, removed records can only shrink the stakes
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut,
+        realloc = UserStakeAccount::space(
+            user_stake_account.stakes.len(),
+            user_stake_account.pending_withdrawals.len() + 1
+        ),
+        realloc::payer = user,
+        realloc::zero = false,
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct DepositRewards<'info> {
+pub struct CancelUnstake<'info> {
     #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+        mut
+    )]
+    pub config: Account<'info, Config>,
+
+    // The pending withdrawal turns back into a stake record
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut,
+        realloc = UserStakeAccount::space(
+            user_stake_account.stakes.len() + 1,
+            user_stake_account.pending_withdrawals.len()
+        ),
+        realloc::payer = user,
+        realloc::zero = false,
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(
+        mint::token_program = token_program
+    )]
+    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CloseStakeAccount<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, config.token_mint_address.as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut,
+        close = user,
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct EarlyUnstake<'info> {
+    #[account(
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
         mut
     )]
-    pub config: Account<'info, Config>,
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [STAKE_VAULT_SEED, config.key().as_ref()],
+        bump = config.stake_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub stake_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
+        mut,
+        token::mint = token_mint,
+        token::authority = config,
+        token::token_program = token_program,
+    )]
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    // One record out, room for the pending withdrawal it may turn into
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut,
+        realloc = UserStakeAccount::space(
+            user_stake_account.stakes.len().saturating_sub(1),
+            user_stake_account.pending_withdrawals.len() + 1
+        ),
+        realloc::payer = user,
+        realloc::zero = false,
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
 
     #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
         mut,
         token::mint = token_mint,
-        token::authority = config,
+        token::authority = user,
         token::token_program = token_program,
     )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 
+    /// Only required when penalties go to the treasury
     #[account(
         mut,
+        address = config.penalty_treasury @ ErrorCode::InvalidTreasury,
         token::mint = token_mint,
-        token::authority = depositor,
         token::token_program = token_program,
     )]
-    pub depositor_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub treasury: Option<Box<InterfaceAccount<'info, TokenAccount>>>,
 
+    // Mutable so penalties can be burned
     #[account(
+        mut,
         mint::token_program = token_program
     )]
     pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
-    pub depositor: Signer<'info>,
-    pub token_program: Program<'info, Token2022>,
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> EarlyUnstake<'info> {
+    /// Move `penalty` out of the vaults holding principal to wherever the admin routed
+    /// penalties. Returns the amount that landed in the reward vault, if any.
+    pub fn route_penalty(
+        &mut self,
+        penalty: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        if penalty == 0 {
+            return Ok(0);
+        }
+
+        let (from_stake_vault, from_reward_vault) = self.config.take_principal(penalty)?;
+
+        match self.config.penalty_destination {
+            PenaltyDestination::RewardVault => {
+                // Compounded principal is already in the reward vault
+                if from_stake_vault == 0 {
+                    return Ok(from_reward_vault);
+                }
+
+                let fee = transfer_from_vault(
+                    &self.token_program.to_account_info(),
+                    &self.stake_vault.to_account_info(),
+                    &self.reward_vault.to_account_info(),
+                    &self.token_mint.to_account_info(),
+                    &self.config,
+                    from_stake_vault,
+                    remaining_accounts,
+                )?;
+                penalty
+                    .checked_sub(fee)
+                    .ok_or(ErrorCode::MathOverflow.into())
+            }
+            PenaltyDestination::Burn => {
+                let pool_id_bytes = self.config.pool_id.to_le_bytes();
+                let seeds: &[&[&[u8]]] = &[&[
+                    CONFIG_PDA_SEED,
+                    self.config.token_mint_address.as_ref(),
+                    &pool_id_bytes,
+                    &[self.config.bump],
+                ]];
+
+                for (vault, amount) in [
+                    (&self.stake_vault, from_stake_vault),
+                    (&self.reward_vault, from_reward_vault),
+                ] {
+                    if amount == 0 {
+                        continue;
+                    }
+
+                    let cpi_accounts = Burn {
+                        mint: self.token_mint.to_account_info(),
+                        from: vault.to_account_info(),
+                        authority: self.config.to_account_info(),
+                    };
+                    let cpi_ctx = CpiContext::new_with_signer(
+                        self.token_program.to_account_info(),
+                        cpi_accounts,
+                        seeds,
+                    );
+
+                    burn(cpi_ctx, amount)?;
+                }
+                Ok(0)
+            }
+            PenaltyDestination::Treasury => {
+                let treasury = self.treasury.as_ref().ok_or(ErrorCode::InvalidTreasury)?;
+
+                for (vault, amount) in [
+                    (&self.stake_vault, from_stake_vault),
+                    (&self.reward_vault, from_reward_vault),
+                ] {
+                    if amount == 0 {
+                        continue;
+                    }
+
+                    transfer_from_vault(
+                        &self.token_program.to_account_info(),
+                        &vault.to_account_info(),
+                        &treasury.to_account_info(),
+                        &self.token_mint.to_account_info(),
+                        &self.config,
+                        amount,
+                        remaining_accounts,
+                    )?;
+                }
+                Ok(0)
+            }
+        }
+    }
+
+    /// Send the principal left after the penalty and the record's rewards to the user, see
+    /// `transfer_payout`.
+    pub fn pay_out(
+        &mut self,
+        principal: u64,
+        reward: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        transfer_payout(
+            &self.token_program.to_account_info(),
+            &self.stake_vault.to_account_info(),
+            &self.reward_vault,
+            &self.user_ata.to_account_info(),
+            &self.token_mint.to_account_info(),
+            &mut self.config,
+            principal,
+            reward,
+            remaining_accounts,
+        )
+    }
 }
 
 #[derive(Accounts)]
-pub struct Withdraw<'info> {
+pub struct ClaimRewards<'info> {
     #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
         mut
     )]
     pub config: Account<'info, Config>,
 
     #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
+        seeds = [REWARD_VAULT_SEED, config.key().as_ref()],
+        bump = config.reward_vault_bump,
         mut,
         token::mint = token_mint,
         token::authority = config,
         token::token_program = token_program,
     )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub reward_vault: Box<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
+        mut
+    )]
+    pub user_stake_account: Account<'info, UserStakeAccount>,
 
     #[account(
         mut,
         token::mint = token_mint,
-        token::authority = authority,
+        token::authority = user,
         token::token_program = token_program,
     )]
-    pub authority_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
 
     #[account(
         mint::token_program = token_program
@@ -679,48 +2460,35 @@ pub struct Withdraw<'info> {
     pub token_mint: Box<InterfaceAccount<'info, Mint>>,
 
     #[account(mut)]
-    pub authority: Signer<'info>,
+    pub user: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
+    pub token_program: Interface<'info, TokenInterface>,
 }
 
 #[derive(Accounts)]
-pub struct Stake<'info> {
+pub struct Compound<'info> {
     #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
+        seeds = [CONFIG_PDA_SEED, token_mint.key().as_ref(), &config.pool_id.to_le_bytes()],
+        bump = config.bump,
         mut
     )]
     pub config: Account<'info, Config>,
 
+    // Room for the compounded record
     #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
+        seeds = [USER_STAKE_SEED, config.key().as_ref(), user.key().as_ref()],
+        bump = user_stake_account.bump,
         mut,
-        token::mint = token_mint,
-        token::authority = config,
-        token::token_program = token_program,
-    )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
-
-    #[account(
-        init,
-        seeds = [user.key().as_ref()],
-        bump,
-        payer = user,
-        space = UserStakeAccount::LEN
+        realloc = UserStakeAccount::space(
+            user_stake_account.stakes.len() + 1,
+            user_stake_account.pending_withdrawals.len()
+        ),
+        realloc::payer = user,
+        realloc::zero = false,
     )]
     pub user_stake_account: Account<'info, UserStakeAccount>,
 
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = user,
-        token::token_program = token_program,
-    )]
-    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
-
     #[account(
         mint::token_program = token_program
     )]
@@ -730,192 +2498,1321 @@ pub struct Stake<'info> {
     pub user: Signer<'info>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+// ----------------------------------------------------------------------------------------------
+//                                  PDAs
+// ----------------------------------------------------------------------------------------------
+
+#[account]
+pub struct Config {
+    pub authority: Pubkey,
+    /// Proposed by `propose_authority`, becomes `authority` once it signs `accept_authority`
+    pub pending_authority: Option<Pubkey>,
+    pub min_stake_period: i64,
+    pub token_mint_address: Pubkey,
+    pub pool_id: u64,
+    pub bump: u8,
+    pub stake_vault_bump: u8,
+    pub reward_vault_bump: u8,
+    /// Annual reward rate in basis points, locked into each record at `stake` time
+    pub reward_rate_bps: u16,
+    /// Rewards emitted per share since inception, scaled by `ACC_PRECISION`. A share earns one
+    /// token per year while the reward budget lasts
+    pub acc_reward_per_share: u128,
+    pub last_reward_time: i64,
+    pub total_staked: u64,
+    /// Sum of `StakeRecord::shares` over every record, what emissions are split across
+    pub total_shares: u64,
+    /// Funded rewards that have not been emitted yet
+    pub reward_budget: u64,
+    /// Rewards emitted to stakers but not paid out yet
+    pub reward_liabilities: u64,
+    /// Emissions and new stakes are halted, see `enable_wind_down`
+    pub wind_down: bool,
+    /// `PAUSE_*` bitmask of halted instructions
+    pub paused: u8,
+    /// Emissions and new stakes are halted, users can `emergency_withdraw` their principal
+    pub emergency: bool,
+    /// Penalty on principal withdrawn before the lock ends, in basis points. Zero keeps
+    /// `early_unstake` disabled
+    pub early_unstake_penalty_bps: u16,
+    /// Scale the penalty down linearly with the time left on the lock
+    pub penalty_linear_decay: bool,
+    pub penalty_destination: PenaltyDestination,
+    /// Token account receiving penalties when `penalty_destination` is `Treasury`
+    pub penalty_treasury: Pubkey,
+    /// Seconds between `request_unstake` and `complete_unstake`, zero allows direct unstaking
+    pub unstake_cooldown: i64,
+    /// Principal waiting out the cooldown, still held in the stake vault
+    pub total_pending_withdrawals: u64,
+    /// Principal staked through `compound`, never moved out of the reward vault. Part of
+    /// `total_staked` and `total_pending_withdrawals`
+    pub compounded_principal: u64,
+    /// `reward_rate_bps` is the target APY on the interest-bearing UI amount, so new records
+    /// only earn what the mint's interest does not cover. The staker's UI amount grows at the
+    /// interest rate on its own, so paying the full rate on top, or paying it on the
+    /// interest-adjusted principal, would give a total return above the advertised APY
+    pub apy_on_ui_amount: bool,
+    /// The mint's `InterestBearingConfig` rate as of the last `sync_interest_rate`, zero for
+    /// mints without the extension
+    pub interest_rate_bps: i16,
+    /// Longer locks on top of the base `min_stake_period` tier, picked at `stake` time
+    pub lock_tiers: Vec<LockTier>,
+}
+
+impl Config {
+    const DISCRIMINATOR: usize = 8;
+    pub const LEN: usize = Self::DISCRIMINATOR
+        + 32
+        + (1 + 32)
+        + 8
+        + 32
+        + 8
+        + 1
+        + 1
+        + 1
+        + 2
+        + 16
+        + 8
+        + 8
+        + 8
+        + 8
+        + 8
+        + 1
+        + 1
+        + 1
+        + 2
+        + 1
+        + 1
+        + 32
+        + 8
+        + 8
+        + 8
+        + 1
+        + 2
+        + (4 + LockTier::LEN * MAX_LOCK_TIERS);
+
+    /// Emit rewards for the time elapsed since `last_reward_time` into `acc_reward_per_share`.
+    ///
+    /// The per-share increment is rounded down while the amount charged to `reward_budget` is
+    /// rounded up, so the sum of all payouts can never exceed what was funded. Once the budget
+    /// is exhausted emissions stop until more rewards are deposited. Emitted rewards move from
+    /// `reward_budget` to `reward_liabilities`. Must run before `total_shares` or
+    /// `reward_budget` change.
+    pub fn update_pool(&mut self, now: i64) -> Result<()> {
+        if now <= self.last_reward_time {
+            return Ok(());
+        }
+
+        let elapsed = (now - self.last_reward_time) as u128;
+        self.last_reward_time = now;
+
+        if self.wind_down || self.emergency || self.total_shares == 0 || self.reward_budget == 0 {
+            return Ok(());
+        }
+
+        let total_shares = self.total_shares as u128;
+
+        // Each record's rate and multiplier are already part of its shares
+        let mut acc_increment = elapsed
+            .checked_mul(ACC_PRECISION)
+            .ok_or(ErrorCode::MathOverflow)?
+            / SECONDS_PER_YEAR;
+        let mut emitted = emission_for(acc_increment, total_shares)?;
+
+        if emitted > self.reward_budget as u128 {
+            acc_increment = (self.reward_budget as u128)
+                .checked_mul(ACC_PRECISION)
+                .ok_or(ErrorCode::MathOverflow)?
+                / total_shares;
+            emitted = emission_for(acc_increment, total_shares)?;
+        }
+
+        self.acc_reward_per_share = self
+            .acc_reward_per_share
+            .checked_add(acc_increment)
+            .ok_or(ErrorCode::MathOverflow)?;
+        // `emitted` is bounded by `reward_budget` at this point
+        self.reward_budget = self
+            .reward_budget
+            .checked_sub(emitted as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.reward_liabilities = self
+            .reward_liabilities
+            .checked_add(emitted as u64)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(())
+    }
+
+    pub fn is_paused(&self, flag: u8) -> bool {
+        self.paused & flag != 0
+    }
+
+    /// Rate a record staked now earns for its whole life, in basis points per year.
+    pub fn emission_rate_bps(&self) -> u16 {
+        if !self.apy_on_ui_amount {
+            return self.reward_rate_bps;
+        }
+
+        // Interest already grows the UI amount, rewards only top it up to the target APY. A
+        // negative rate can push the difference past `u16::MAX`
+        (self.reward_rate_bps as i32 - self.interest_rate_bps as i32).clamp(0, u16::MAX as i32)
+            as u16
+    }
+
+    /// Cache the mint's current interest rate. Call before `emission_rate_bps` is locked into
+    /// a new record.
+    pub fn sync_interest_rate(&mut self, mint: &AccountInfo) -> Result<()> {
+        self.interest_rate_bps = interest_rate_bps(mint)?;
+        Ok(())
+    }
+
+    /// Penalty charged on `principal` withdrawn with `remaining_lock` seconds of a
+    /// `lock_duration` lock left, rounded up in favour of the pool.
+    pub fn early_unstake_penalty(
+        &self,
+        principal: u64,
+        remaining_lock: i64,
+        lock_duration: i64,
+    ) -> Result<u64> {
+        if remaining_lock <= 0 || lock_duration <= 0 {
+            return Ok(0);
+        }
+
+        let (numerator, denominator) = if self.penalty_linear_decay {
+            (
+                (self.early_unstake_penalty_bps as u128)
+                    .checked_mul(remaining_lock.min(lock_duration) as u128)
+                    .ok_or(ErrorCode::MathOverflow)?,
+                BPS_DENOMINATOR * lock_duration as u128,
+            )
+        } else {
+            (self.early_unstake_penalty_bps as u128, BPS_DENOMINATOR)
+        };
+
+        let penalty = (principal as u128)
+            .checked_mul(numerator)
+            .ok_or(ErrorCode::MathOverflow)?
+            .div_ceil(denominator);
+
+        // The penalty rate is at most 100%, so this never exceeds `principal`
+        Ok(penalty as u64)
+    }
+
+    /// The base tier for `None`, otherwise one of `lock_tiers`.
+    pub fn lock_tier(&self, tier: Option<u8>) -> Result<LockTier> {
+        match tier {
+            None => Ok(LockTier {
+                lock_period: self.min_stake_period,
+                multiplier_bps: BPS_DENOMINATOR as u16,
+            }),
+            Some(index) => self
+                .lock_tiers
+                .get(index as usize)
+                .copied()
+                .ok_or(ErrorCode::InvalidLockTier.into()),
+        }
+    }
+
+    /// Book principal and the shares it earns on entering the pool. Call `update_pool` first.
+    pub fn add_staked(&mut self, amount: u64, shares: u64) -> Result<()> {
+        self.total_staked = self
+            .total_staked
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_shares = self
+            .total_shares
+            .checked_add(shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Book principal and its shares leaving the pool. Call `update_pool` first.
+    pub fn remove_staked(&mut self, amount: u64, shares: u64) -> Result<()> {
+        self.total_staked = self
+            .total_staked
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.total_shares = self
+            .total_shares
+            .checked_sub(shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Split `principal` leaving the pool into what the stake vault and the reward vault pay.
+    /// Compounded principal goes first, so the stake vault always covers the rest.
+    pub fn take_principal(&mut self, principal: u64) -> Result<(u64, u64)> {
+        let from_reward_vault = principal.min(self.compounded_principal);
+        self.compounded_principal = self
+            .compounded_principal
+            .checked_sub(from_reward_vault)
+            .ok_or(ErrorCode::MathOverflow)?;
+        let from_stake_vault = principal
+            .checked_sub(from_reward_vault)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok((from_stake_vault, from_reward_vault))
+    }
+
+    /// Restake everything `user_stake_account` has earned as a new record in `lock_tier`.
+    /// The rewards never leave the reward vault, so no transfer fee is charged. They are tracked
+    /// as principal held there until the stake is paid out.
+    pub fn compound(
+        &mut self,
+        user_stake_account: &mut UserStakeAccount,
+        tier: Option<u8>,
+        lock_tier: &LockTier,
+        now: i64,
+    ) -> Result<StakeRecord> {
+        let amount = user_stake_account.pending_reward(self.acc_reward_per_share)?;
+        require!(amount > 0, ErrorCode::NothingToCompound);
+
+        user_stake_account.settle(self.acc_reward_per_share)?;
+        self.release_liability(amount)?;
+
+        self.compounded_principal = self
+            .compounded_principal
+            .checked_add(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        let stake = user_stake_account.add_stake(
+            amount,
+            tier,
+            lock_tier,
+            self.emission_rate_bps(),
+            now,
+            self.acc_reward_per_share,
+        )?;
+
+        self.add_staked(stake.amount, stake.shares)?;
+
+        Ok(stake)
+    }
+
+    /// Book `amount` of emitted rewards as paid out to a staker.
+    pub fn release_liability(&mut self, amount: u64) -> Result<()> {
+        self.reward_liabilities = self
+            .reward_liabilities
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        Ok(())
+    }
+}
+
+// Bits of `Config::paused`
+pub const PAUSE_STAKE: u8 = 1 << 0;
+pub const PAUSE_CLAIM: u8 = 1 << 1;
+pub const PAUSE_UNSTAKE: u8 = 1 << 2;
+pub const PAUSE_DEPOSIT: u8 = 1 << 3;
+pub const PAUSE_ALL: u8 = PAUSE_STAKE | PAUSE_CLAIM | PAUSE_UNSTAKE | PAUSE_DEPOSIT;
+
+/// Lock length and reward weight a stake record is created with.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct LockTier {
+    /// Days, like `Config::min_stake_period`
+    pub lock_period: i64,
+    /// Scales the reward rate in basis points, `BPS_DENOMINATOR` earns `reward_rate_bps` as is
+    pub multiplier_bps: u16,
+}
+
+impl LockTier {
+    pub const LEN: usize = 8 + 2;
+
+    pub fn lock_seconds(&self) -> Result<i64> {
+        self.lock_period
+            .checked_mul(SECONDS_PER_DAY)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    /// Reward shares `amount` earns in this tier at `reward_rate_bps`, i.e. its annual reward,
+    /// rounded down.
+    pub fn shares_for(&self, amount: u64, reward_rate_bps: u16) -> Result<u64> {
+        let shares = (amount as u128)
+            .checked_mul(self.multiplier_bps as u128)
+            .and_then(|value| value.checked_mul(reward_rate_bps as u128))
+            .ok_or(ErrorCode::MathOverflow)?
+            / (BPS_DENOMINATOR * BPS_DENOMINATOR);
+        u64::try_from(shares).map_err(|_| ErrorCode::MathOverflow.into())
+    }
+}
+
+pub const MAX_LOCK_TIERS: usize = 8;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct StakeRecord {
+    /// Unique within the owning `UserStakeAccount`, never reused
+    pub id: u64,
+    pub amount: u64,
+    /// What the record earns on, `amount` scaled by its tier multiplier and reward rate
+    pub shares: u64,
+    pub timestamp: i64,
+    /// Index into `Config::lock_tiers`, `None` for the base tier
+    pub tier: Option<u8>,
+    /// `Config::emission_rate_bps` when the record was created, later rate changes leave it be
+    pub reward_rate_bps: u16,
+    /// Fixed at creation, later lock period changes leave it be
+    pub unlock_time: i64,
+    /// `Config::acc_reward_per_share` when the record was created
+    pub entry_acc_per_share: u128,
+}
+
+impl StakeRecord {
+    pub const LEN: usize = 8 + 8 + 8 + 8 + (1 + 1) + 2 + 8 + 16;
+
+    /// A record locking `amount` in `lock_tier` at `reward_rate_bps` from `now`.
+    pub fn new(
+        id: u64,
+        amount: u64,
+        tier: Option<u8>,
+        lock_tier: &LockTier,
+        reward_rate_bps: u16,
+        now: i64,
+        acc_reward_per_share: u128,
+    ) -> Result<Self> {
+        Ok(Self {
+            id,
+            amount,
+            shares: lock_tier.shares_for(amount, reward_rate_bps)?,
+            timestamp: now,
+            tier,
+            reward_rate_bps,
+            unlock_time: now
+                .checked_add(lock_tier.lock_seconds()?)
+                .ok_or(ErrorCode::MathOverflow)?,
+            entry_acc_per_share: acc_reward_per_share,
+        })
+    }
+
+    pub fn is_matured(&self, now: i64) -> bool {
+        now >= self.unlock_time
+    }
+
+    /// Seconds until the record unlocks, zero once it has.
+    pub fn remaining_lock(&self, now: i64) -> i64 {
+        self.unlock_time.saturating_sub(now).max(0)
+    }
+
+    pub fn lock_duration(&self) -> i64 {
+        self.unlock_time.saturating_sub(self.timestamp)
+    }
+}
+
+/// Principal that left the pool through `request_unstake` and no longer earns rewards.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct PendingWithdrawal {
+    /// Drawn from the same counter as stake record ids
+    pub id: u64,
+    pub amount: u64,
+    /// Earliest time `complete_unstake` releases the tokens
+    pub unlock_time: i64,
+}
+
+impl PendingWithdrawal {
+    pub const LEN: usize = 8 + 8 + 8;
+}
+
+/// Where early unstake penalties are sent.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum PenaltyDestination {
+    /// Added to the reward budget and emitted to the remaining stakers
+    RewardVault,
+    /// Burned from the stake vault
+    Burn,
+    /// Sent to `Config::penalty_treasury`
+    Treasury,
+}
+
+/// Which matured records `unstake_amount` consumes first.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub enum UnstakeOrder {
+    /// Oldest records first
+    Fifo,
+    /// Newest records first
+    Lifo,
+}
+
+#[account]
+pub struct UserStakeAccount {
+    pub authority: Pubkey,
+    pub bump: u8,
+    /// Sum of `stakes[..].amount`
+    pub staked_amount: u64,
+    /// Sum of `stakes[..].shares`
+    pub shares: u64,
+    /// Sum over records of `shares * max(entry_acc_per_share, settled_acc_per_share)`,
+    /// scaled by `ACC_PRECISION`
+    pub reward_debt: u128,
+    /// Rewards settled out of relocked records, paid with the next settlement
+    pub unclaimed_rewards: u64,
+    /// `Config::acc_reward_per_share` at the last full settlement
+    pub settled_acc_per_share: u128,
+    pub next_stake_id: u64,
+    pub stakes: Vec<StakeRecord>,
+    pub pending_withdrawals: Vec<PendingWithdrawal>,
+}
+
+impl UserStakeAccount {
+    pub const DISCRIMINATOR: usize = 8;
+    pub const VECTOR_LENGTH_PREFIX: usize = 4;
+
+    /// An empty account, every record is paid for by the `stake` that adds it
+    pub const LEN: usize = Self::DISCRIMINATOR
+        + 32
+        + 1
+        + 8
+        + 8
+        + 16
+        + 8
+        + 16
+        + 8
+        + Self::VECTOR_LENGTH_PREFIX
+        + Self::VECTOR_LENGTH_PREFIX;
+
+    /// Account size holding `stake_count` records and `pending_count` pending withdrawals.
+    pub fn space(stake_count: usize, pending_count: usize) -> usize {
+        Self::LEN + StakeRecord::LEN * stake_count + PendingWithdrawal::LEN * pending_count
+    }
+
+    /// Rewards accrued since the last settlement, including those kept from relocked records.
+    pub fn pending_reward(&self, acc_reward_per_share: u128) -> Result<u64> {
+        calculate_reward(self.shares, acc_reward_per_share, self.reward_debt)?
+            .checked_add(self.unclaimed_rewards)
+            .ok_or(ErrorCode::MathOverflow.into())
+    }
+
+    /// Record a new stake in `lock_tier` at `reward_rate_bps` without changing the rewards
+    /// accrued so far and return the new record.
+    pub fn add_stake(
+        &mut self,
+        amount: u64,
+        tier: Option<u8>,
+        lock_tier: &LockTier,
+        reward_rate_bps: u16,
+        now: i64,
+        acc_reward_per_share: u128,
+    ) -> Result<StakeRecord> {
+        let stake = StakeRecord::new(
+            self.next_stake_id,
+            amount,
+            tier,
+            lock_tier,
+            reward_rate_bps,
+            now,
+            acc_reward_per_share,
+        )?;
+
+        self.insert_stake(self.stakes.len(), stake.clone())?;
+        self.next_stake_id = self
+            .next_stake_id
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(stake)
+    }
+
+    /// Relock the record at `index` into `lock_tier` at `reward_rate_bps` from `now`, keeping
+    /// its id, principal and position. The reward it accrued so far moves to
+    /// `unclaimed_rewards`. Returns the previous record, the new one and that reward.
+    pub fn relock_stake(
+        &mut self,
+        index: usize,
+        tier: Option<u8>,
+        lock_tier: &LockTier,
+        reward_rate_bps: u16,
+        now: i64,
+        acc_reward_per_share: u128,
+    ) -> Result<(StakeRecord, StakeRecord, u64)> {
+        let stake = StakeRecord::new(
+            self.stakes[index].id,
+            self.stakes[index].amount,
+            tier,
+            lock_tier,
+            reward_rate_bps,
+            now,
+            acc_reward_per_share,
+        )?;
+
+        require!(
+            stake.unlock_time >= self.stakes[index].unlock_time,
+            ErrorCode::LockNotExtended
+        );
+
+        let (previous, reward) = self.remove_stake(index, acc_reward_per_share)?;
+        self.unclaimed_rewards = self
+            .unclaimed_rewards
+            .checked_add(reward)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.insert_stake(index, stake.clone())?;
+
+        Ok((previous, stake, reward))
+    }
+
+    /// Insert a record created at the current `acc_reward_per_share`, so it has nothing accrued
+    /// yet.
+    fn insert_stake(&mut self, index: usize, stake: StakeRecord) -> Result<()> {
+        self.staked_amount = self
+            .staked_amount
+            .checked_add(stake.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.shares = self
+            .shares
+            .checked_add(stake.shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.reward_debt = self
+            .reward_debt
+            .checked_add(self.record_reward_debt(&stake)?)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        self.stakes.insert(index, stake);
+
+        Ok(())
+    }
+
+    /// Queue `amount` for release at `unlock_time` and return the withdrawal id.
+    pub fn add_pending_withdrawal(&mut self, amount: u64, unlock_time: i64) -> Result<u64> {
+        let id = self.next_stake_id;
+
+        self.pending_withdrawals.push(PendingWithdrawal {
+            id,
+            amount,
+            unlock_time,
+        });
+        self.next_stake_id = self
+            .next_stake_id
+            .checked_add(1)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(id)
+    }
+
+    pub fn find_pending_withdrawal(&self, withdrawal_id: u64) -> Result<usize> {
+        self.pending_withdrawals
+            .iter()
+            .position(|withdrawal| withdrawal.id == withdrawal_id)
+            .ok_or(ErrorCode::PendingWithdrawalNotFound.into())
+    }
+
+    pub fn find_stake(&self, stake_id: u64) -> Result<usize> {
+        self.stakes
+            .iter()
+            .position(|stake| stake.id == stake_id)
+            .ok_or(ErrorCode::StakeRecordNotFound.into())
+    }
+
+    /// `max(entry_acc_per_share, settled_acc_per_share)` scaled by the record shares, i.e. the
+    /// record's share of `reward_debt`.
+    fn record_reward_debt(&self, stake: &StakeRecord) -> Result<u128> {
+        scaled_reward(
+            stake.shares,
+            stake.entry_acc_per_share.max(self.settled_acc_per_share),
+        )
+    }
+
+    /// Remove the record at `index` and return it with its accrued reward, leaving the rewards
+    /// of every other record untouched.
+    pub fn remove_stake(
+        &mut self,
+        index: usize,
+        acc_reward_per_share: u128,
+    ) -> Result<(StakeRecord, u64)> {
+        let record_debt = self.record_reward_debt(&self.stakes[index])?;
+        let stake = self.stakes.remove(index);
+
+        let reward = calculate_reward(stake.shares, acc_reward_per_share, record_debt)?;
+
+        self.staked_amount = self
+            .staked_amount
+            .checked_sub(stake.amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.shares = self
+            .shares
+            .checked_sub(stake.shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.reward_debt = self
+            .reward_debt
+            .checked_sub(record_debt)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok((stake, reward))
+    }
+
+    /// Hand out `unclaimed_rewards` once the last record has been removed, since nothing would
+    /// settle them afterwards.
+    pub fn take_unclaimed_if_last(&mut self) -> u64 {
+        if !self.stakes.is_empty() {
+            return 0;
+        }
+
+        std::mem::take(&mut self.unclaimed_rewards)
+    }
+
+    /// Principal held in records past their unlock time.
+    pub fn matured_amount(&self, now: i64) -> Result<u64> {
+        let mut total: u64 = 0;
+        for stake in self.stakes.iter() {
+            if stake.is_matured(now) {
+                total = total
+                    .checked_add(stake.amount)
+                    .ok_or(ErrorCode::MathOverflow)?;
+            }
+        }
+        Ok(total)
+    }
+
+    /// Remove exactly `amount` of matured principal, consuming records in `order`, and return
+    /// the shares removed with it. A partially consumed record keeps its remainder, tier and
+    /// unlock time with its shares scaled down, immature records are never touched.
+    /// Rewards are not settled here, callers must `settle` afterwards.
+    pub fn remove_matured(&mut self, amount: u64, order: UnstakeOrder, now: i64) -> Result<u64> {
+        let mut remaining = amount;
+        let mut removed_shares: u64 = 0;
+
+        let indices: Vec<usize> = match order {
+            UnstakeOrder::Fifo => (0..self.stakes.len()).collect(),
+            UnstakeOrder::Lifo => (0..self.stakes.len()).rev().collect(),
+        };
+
+        for index in indices {
+            if remaining == 0 {
+                break;
+            }
+
+            // Empty records hold no principal and would divide by zero below
+            let stake = &mut self.stakes[index];
+            if stake.amount == 0 || !stake.is_matured(now) {
+                continue;
+            }
+
+            let taken = stake.amount.min(remaining);
+            let kept_amount = stake
+                .amount
+                .checked_sub(taken)
+                .ok_or(ErrorCode::MathOverflow)?;
+            let kept_shares = (stake.shares as u128)
+                .checked_mul(kept_amount as u128)
+                .and_then(|value| value.checked_div(stake.amount as u128))
+                .and_then(|value| u64::try_from(value).ok())
+                .ok_or(ErrorCode::MathOverflow)?;
+
+            removed_shares = stake
+                .shares
+                .checked_sub(kept_shares)
+                .and_then(|value| removed_shares.checked_add(value))
+                .ok_or(ErrorCode::MathOverflow)?;
+            stake.shares = kept_shares;
+            stake.amount = kept_amount;
+            remaining = remaining
+                .checked_sub(taken)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        require!(remaining == 0, ErrorCode::InsufficientMaturedStake);
+
+        self.stakes.retain(|stake| stake.amount > 0);
+        self.staked_amount = self
+            .staked_amount
+            .checked_sub(amount)
+            .ok_or(ErrorCode::MathOverflow)?;
+        self.shares = self
+            .shares
+            .checked_sub(removed_shares)
+            .ok_or(ErrorCode::MathOverflow)?;
+
+        Ok(removed_shares)
+    }
+
+    /// Drop every record and pending withdrawal, returning their principal. Rewards are not
+    /// paid, whatever was pending is forfeited.
+    pub fn remove_all(&mut self) -> Result<(u64, u64)> {
+        let staked = self.staked_amount;
+        let mut pending_withdrawals: u64 = 0;
+        for withdrawal in self.pending_withdrawals.iter() {
+            pending_withdrawals = pending_withdrawals
+                .checked_add(withdrawal.amount)
+                .ok_or(ErrorCode::MathOverflow)?;
+        }
+
+        self.stakes.clear();
+        self.pending_withdrawals.clear();
+        self.staked_amount = 0;
+        self.shares = 0;
+        self.reward_debt = 0;
+        self.unclaimed_rewards = 0;
+
+        Ok((staked, pending_withdrawals))
+    }
+
+    /// No principal, pending withdrawals or rewards left, so the account can be closed.
+    /// Rewards only accrue on the records' shares, so only `unclaimed_rewards` can be owed once
+    /// they are gone.
+    pub fn is_empty(&self) -> bool {
+        self.staked_amount == 0
+            && self.stakes.is_empty()
+            && self.pending_withdrawals.is_empty()
+            && self.unclaimed_rewards == 0
+    }
+
+    /// Mark every reward accrued so far as paid out.
+    pub fn settle(&mut self, acc_reward_per_share: u128) -> Result<()> {
+        self.unclaimed_rewards = 0;
+        self.reward_debt = scaled_reward(self.shares, acc_reward_per_share)?;
+        self.settled_acc_per_share = acc_reward_per_share;
+        Ok(())
+    }
+}
+
+// ----------------------------------------------------------------------------------------------
+//                                  MATH
+// ----------------------------------------------------------------------------------------------
+
+pub const SECONDS_PER_DAY: i64 = 86_400;
+pub const SECONDS_PER_YEAR: u128 = 365 * 86_400;
+pub const BPS_DENOMINATOR: u128 = 10_000;
+/// Fixed-point scale of `Config::acc_reward_per_share`
+pub const ACC_PRECISION: u128 = 1_000_000_000_000;
+
+/// `shares * acc_reward_per_share`, kept at `ACC_PRECISION` scale.
+pub fn scaled_reward(shares: u64, acc_reward_per_share: u128) -> Result<u128> {
+    (shares as u128)
+        .checked_mul(acc_reward_per_share)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+/// Reward owed to `shares` at `acc_reward_per_share` net of `reward_debt`.
+///
+/// Everything stays at `ACC_PRECISION` scale until the single final division, which rounds
+/// down in favour of the pool, so every payout is reproducible off-chain to the base unit.
+pub fn calculate_reward(shares: u64, acc_reward_per_share: u128, reward_debt: u128) -> Result<u64> {
+    let reward = scaled_reward(shares, acc_reward_per_share)?
+        .checked_sub(reward_debt)
+        .ok_or(ErrorCode::MathOverflow)?
+        / ACC_PRECISION;
+
+    u64::try_from(reward).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+/// Tokens emitted when `acc_reward_per_share` grows by `acc_increment` over `total_shares`,
+/// rounded up.
+fn emission_for(acc_increment: u128, total_shares: u128) -> Result<u128> {
+    Ok(acc_increment
+        .checked_mul(total_shares)
+        .ok_or(ErrorCode::MathOverflow)?
+        .div_ceil(ACC_PRECISION))
+}
+
+/// Transfer fee the mint withholds on `amount` in the current epoch, using the basis points
+/// and maximum fee configured on the mint itself. `None` when the mint has no
+/// `TransferFeeConfig`, which includes every SPL Token mint.
+pub fn calculate_transfer_fee(mint: &AccountInfo, amount: u64) -> Result<Option<u64>> {
+    // SPL Token mints unpack as a Token-2022 base mint without extensions
+    let mint_data = mint.data.borrow();
+    let state_with_extensions =
+        StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    let Ok(transfer_fee_config) = state_with_extensions.get_extension::<TransferFeeConfig>() else {
+        return Ok(None);
+    };
+
+    // Fee changes take effect at an epoch boundary, pick whichever is active now
+    let current_epoch = Clock::get()?.epoch;
+
+    transfer_fee_config
+        .calculate_epoch_fee(current_epoch, amount)
+        .map(Some)
+        .ok_or(ErrorCode::MathOverflow.into())
+}
+
+pub fn has_transfer_fee_config(mint: &AccountInfo) -> Result<bool> {
+    let mint_data = mint.data.borrow();
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    Ok(state.get_extension::<TransferFeeConfig>().is_ok())
+}
+
+pub fn mint_decimals(mint: &AccountInfo) -> Result<u8> {
+    let mint_data = mint.data.borrow();
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    Ok(state.base.decimals)
+}
+
+/// Current `InterestBearingConfig` rate of the mint, zero when it has no such extension.
+pub fn interest_rate_bps(mint: &AccountInfo) -> Result<i16> {
+    let mint_data = mint.data.borrow();
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+
+    Ok(state
+        .get_extension::<InterestBearingConfig>()
+        .map(|interest| interest.current_rate.into())
+        .unwrap_or(0))
+}
+
+/// `amount` as the wallet would display it at `unix_timestamp`: scaled by the mint decimals and,
+/// for interest-bearing mints, including the interest accrued so far.
+pub fn ui_amount(mint: &AccountInfo, amount: u64, unix_timestamp: i64) -> Result<String> {
+    let mint_data = mint.data.borrow();
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let decimals = state.base.decimals;
+
+    match state.get_extension::<InterestBearingConfig>() {
+        Ok(interest) => interest
+            .amount_to_ui_amount(amount, decimals, unix_timestamp)
+            .ok_or(ErrorCode::MathOverflow.into()),
+        Err(_) => Ok(amount_to_ui_amount_string_trimmed(amount, decimals)),
+    }
+}
+
+/// Program the mint's `TransferHook` extension points at, if any.
+pub fn transfer_hook_program_id(mint: &AccountInfo) -> Result<Option<Pubkey>> {
+    let mint_data = mint.data.borrow();
+    let state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    Ok(transfer_hook::get_program_id(&state))
 }
 
-#[derive(Accounts)]
-pub struct StakeRealloc<'info> {
-    #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
-        mut
-    )]
-    pub config: Account<'info, Config>,
+// ----------------------------------------------------------------------------------------------
+//                                  TRANSFERS
+// ----------------------------------------------------------------------------------------------
 
-    #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
-        mut,
-        token::mint = token_mint,
-        token::authority = config,
-        token::token_program = token_program,
-    )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+/// Transfer `amount` from a user-owned account into one of the pool vaults.
+/// Returns the transfer fee withheld by the mint, i.e. `amount - fee` reaches the vault.
+pub fn transfer_to_vault<'info>(
+    token_program: &AccountInfo<'info>,
+    source: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    amount: u64,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    transfer_tokens(
+        token_program,
+        source,
+        vault,
+        authority,
+        mint,
+        &[],
+        amount,
+        remaining_accounts,
+    )
+}
 
-    #[account(
-        seeds = [user.key().as_ref()],
-        bump,
-        mut,
-        realloc = UserStakeAccount::LEN  + std::mem::size_of_val(&user_stake_account) + std::mem::size_of::<UserStakeAccount>(),
-        realloc::payer = user,
-        realloc::zero = false,
-    )]
-    pub user_stake_account: Account<'info, UserStakeAccount>,
+/// Transfer `amount` out of one of the pool vaults, signed by the config PDA.
+/// Returns the transfer fee withheld by the mint.
+pub fn transfer_from_vault<'info>(
+    token_program: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    config: &Account<'info, Config>,
+    amount: u64,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    let pool_id_bytes = config.pool_id.to_le_bytes();
+    let seeds: &[&[&[u8]]] = &[&[
+        CONFIG_PDA_SEED,
+        config.token_mint_address.as_ref(),
+        &pool_id_bytes,
+        &[config.bump],
+    ]];
+
+    transfer_tokens(
+        token_program,
+        vault,
+        destination,
+        &config.to_account_info(),
+        mint,
+        seeds,
+        amount,
+        remaining_accounts,
+    )
+}
 
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = user,
-        token::token_program = token_program,
-    )]
-    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+/// Send `principal` and `reward` leaving the pool to `user_ata`, returning the transfer fees
+/// withheld. Principal comes from the stake vault, except compounded principal which is paid
+/// from the reward vault in the same transfer as the rewards.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_payout<'info>(
+    token_program: &AccountInfo<'info>,
+    stake_vault: &AccountInfo<'info>,
+    reward_vault: &InterfaceAccount<'info, TokenAccount>,
+    user_ata: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    config: &mut Account<'info, Config>,
+    principal: u64,
+    reward: u64,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    let (from_stake_vault, from_reward_vault) = config.take_principal(principal)?;
+    let from_reward_vault = from_reward_vault
+        .checked_add(reward)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    // Ensure there are enough rewards in the contract
+    if reward_vault.amount < from_reward_vault {
+        return Err(ErrorCode::InsufficientRewards.into());
+    }
 
-    #[account(
-        mint::token_program = token_program
-    )]
-    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+    let mut fee: u64 = 0;
+
+    if from_stake_vault > 0 {
+        fee = transfer_from_vault(
+            token_program,
+            stake_vault,
+            user_ata,
+            mint,
+            config,
+            from_stake_vault,
+            remaining_accounts,
+        )?;
+    }
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+    if from_reward_vault > 0 {
+        let reward_fee = transfer_from_vault(
+            token_program,
+            &reward_vault.to_account_info(),
+            user_ata,
+            mint,
+            config,
+            from_reward_vault,
+            remaining_accounts,
+        )?;
+        fee = fee.checked_add(reward_fee).ok_or(ErrorCode::MathOverflow)?;
+    }
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
+    Ok(fee)
 }
 
-#[derive(Accounts)]
-pub struct Unstake<'info> {
-    #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
-        mut
-    )]
-    pub config: Account<'info, Config>,
+/// `transfer_checked_with_fee` when the mint charges a transfer fee, plain `transfer_checked`
+/// otherwise, so both token programs and fee-less Token-2022 mints are supported.
+///
+/// When the mint has a transfer hook, the hook program, its `ExtraAccountMetaList` and the
+/// accounts it lists are resolved from `remaining_accounts` and appended to the CPI.
+#[allow(clippy::too_many_arguments)]
+fn transfer_tokens<'info>(
+    token_program: &AccountInfo<'info>,
+    source: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    authority: &AccountInfo<'info>,
+    mint: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    amount: u64,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Result<u64> {
+    let decimals = mint_decimals(mint)?;
+    let fee = calculate_transfer_fee(mint, amount)?;
+    let hook_program_id = transfer_hook_program_id(mint)?;
+
+    let mut instruction = match fee {
+        Some(fee) => transfer_fee_instruction::transfer_checked_with_fee(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+            fee,
+        )?,
+        None => token_instruction::transfer_checked(
+            token_program.key,
+            source.key,
+            mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?,
+    };
+
+    let mut account_infos = vec![
+        source.clone(),
+        mint.clone(),
+        destination.clone(),
+        authority.clone(),
+    ];
+
+    if let Some(program_id) = hook_program_id {
+        add_extra_accounts_for_execute_cpi(
+            &mut instruction,
+            &mut account_infos,
+            &program_id,
+            source.clone(),
+            mint.clone(),
+            destination.clone(),
+            authority.clone(),
+            amount,
+            remaining_accounts,
+        )?;
+    }
 
-    #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
-        mut,
-        token::mint = token_mint,
-        token::authority = config,
-        token::token_program = token_program,
-    )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+    account_infos.push(token_program.clone());
+    invoke_signed(&instruction, &account_infos, signer_seeds)?;
 
-    #[account(
-        seeds = [user.key().as_ref()],
-        bump,
-        mut
-    )]
-    pub user_stake_account: Account<'info, UserStakeAccount>,
+    Ok(fee.unwrap_or(0))
+}
 
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = user,
-        token::token_program = token_program,
-    )]
-    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+// ----------------------------------------------------------------------------------------------
+//                                  EVENTS
+// ----------------------------------------------------------------------------------------------
 
-    #[account(
-        mint::token_program = token_program
-    )]
-    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+// `fee` is always the Token-2022 transfer fee withheld on the transfers the instruction made,
+// zero for mints without a transfer fee. Every `*ui_amount` is the raw amount next to it as
+// the wallet displays it at `timestamp`, see `ui_amount`
+
+#[event]
+pub struct Staked {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub stake_id: u64,
+    pub tier: Option<u8>,
+    pub reward_rate_bps: u16,
+    pub shares: u64,
+    pub unlock_time: i64,
+    /// Sent by the user, `amount - fee` was staked
+    pub amount: u64,
+    pub ui_amount: String,
+    pub fee: u64,
+    pub user_staked_amount: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+#[event]
+pub struct StakeExtended {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub stake_id: u64,
+    pub tier: Option<u8>,
+    pub reward_rate_bps: u16,
+    pub shares: u64,
+    pub previous_unlock_time: i64,
+    pub unlock_time: i64,
+    /// Accrued by the record under its previous terms, now part of `unclaimed_rewards`
+    pub settled_reward: u64,
+    pub unclaimed_rewards: u64,
+    pub timestamp: i64,
+}
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
+#[event]
+pub struct Unstaked {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    /// Set when a single record was unstaked
+    pub stake_id: Option<u64>,
+    /// Principal removed from the pool, `principal - penalty` was sent to the user
+    pub principal: u64,
+    pub principal_ui_amount: String,
+    pub reward: u64,
+    pub reward_ui_amount: String,
+    pub penalty: u64,
+    /// Set when `principal - penalty` was queued behind the cooldown instead of sent
+    pub withdrawal_id: Option<u64>,
+    pub fee: u64,
+    pub user_staked_amount: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
 }
 
-#[derive(Accounts)]
-pub struct ClaimRewards<'info> {
-    #[account(
-        seeds = [CONFIG_PDA_SEED.as_ref()],
-        bump,
-        mut
-    )]
-    pub config: Account<'info, Config>,
+#[event]
+pub struct UnstakeRequested {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub withdrawal_id: u64,
+    pub amount: u64,
+    pub ui_amount: String,
+    pub unlock_time: i64,
+    /// Rewards paid out when the principal stopped earning
+    pub reward: u64,
+    pub reward_ui_amount: String,
+    pub fee: u64,
+    pub user_staked_amount: u64,
+    pub total_staked: u64,
+    pub total_pending_withdrawals: u64,
+    pub timestamp: i64,
+}
 
-    #[account(
-        seeds = [CONFIG_ATA_SEED.as_ref()],
-        bump,
-        mut,
-        token::mint = token_mint,
-        token::authority = config,
-        token::token_program = token_program,
-    )]
-    pub config_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+#[event]
+pub struct UnstakeCompleted {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub withdrawal_id: u64,
+    pub amount: u64,
+    pub ui_amount: String,
+    pub fee: u64,
+    pub total_pending_withdrawals: u64,
+    pub timestamp: i64,
+}
 
-    #[account(
-        seeds = [user.key().as_ref()],
-        bump,
-        mut
-    )]
-    pub user_stake_account: Account<'info, UserStakeAccount>,
+#[event]
+pub struct UnstakeCancelled {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub withdrawal_id: u64,
+    /// Record the pending withdrawal was re-staked as
+    pub stake_id: u64,
+    pub amount: u64,
+    pub ui_amount: String,
+    pub user_staked_amount: u64,
+    pub total_staked: u64,
+    pub total_pending_withdrawals: u64,
+    pub timestamp: i64,
+}
 
-    #[account(
-        mut,
-        token::mint = token_mint,
-        token::authority = user,
-        token::token_program = token_program,
-    )]
-    pub user_ata: Box<InterfaceAccount<'info, TokenAccount>>,
+#[event]
+pub struct EmergencyWithdrawn {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    /// Staked and pending principal returned
+    pub principal: u64,
+    pub principal_ui_amount: String,
+    /// Unpaid rewards returned to the budget
+    pub forfeited_reward: u64,
+    pub fee: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
 
-    #[account(
-        mint::token_program = token_program
-    )]
-    pub token_mint: Box<InterfaceAccount<'info, Mint>>,
+#[event]
+pub struct StakeAccountOpened {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
 
-    #[account(mut)]
-    pub user: Signer<'info>,
+#[event]
+pub struct StakeAccountClosed {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub timestamp: i64,
+}
 
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token2022>,
+#[event]
+pub struct RewardsClaimed {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub amount: u64,
+    pub ui_amount: String,
+    pub fee: u64,
+    pub reward_liabilities: u64,
+    pub timestamp: i64,
 }
 
-// ----------------------------------------------------------------------------------------------
-//                                  PDAs
-// ----------------------------------------------------------------------------------------------
+#[event]
+pub struct Compounded {
+    pub config: Pubkey,
+    pub user: Pubkey,
+    pub stake_id: u64,
+    pub tier: Option<u8>,
+    pub reward_rate_bps: u16,
+    pub shares: u64,
+    pub unlock_time: i64,
+    /// Rewards turned into principal, no fee is charged
+    pub amount: u64,
+    pub ui_amount: String,
+    pub user_staked_amount: u64,
+    pub total_staked: u64,
+    pub timestamp: i64,
+}
 
-#[account]
-pub struct Config {
+#[event]
+pub struct RewardsDeposited {
+    pub config: Pubkey,
+    pub depositor: Pubkey,
+    /// Sent by the depositor, `amount - fee` was added to the budget
+    pub amount: u64,
+    pub ui_amount: String,
+    pub fee: u64,
+    pub reward_budget: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub config: Pubkey,
     pub authority: Pubkey,
-    pub min_stake_period: i64,
-    pub token_mint_address: Pubkey,
-    pub decimals: u8,
-    pub tax_percentage: u8,
+    pub amount: u64,
+    pub ui_amount: String,
+    pub fee: u64,
+    pub reward_budget: u64,
+    pub timestamp: i64,
 }
 
-impl Config {
-    const DISCRIMINATOR: usize = 8;
-    pub const LEN: usize = Self::DISCRIMINATOR + 32 + 8 + 32 + 1 + 1;
+#[event]
+pub struct WithheldFeesHarvested {
+    pub config: Pubkey,
+    /// Token accounts harvested, the two vaults included
+    pub source_count: u32,
+    pub timestamp: i64,
 }
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct StakeRecord {
+#[event]
+pub struct WithheldFeesWithdrawn {
+    pub config: Pubkey,
     pub amount: u64,
+    pub ui_amount: String,
+    pub reward_budget: u64,
     pub timestamp: i64,
 }
 
-impl StakeRecord {
-    pub const LEN: usize = 8 + 8;
+#[event]
+pub struct AuthorityProposed {
+    pub config: Pubkey,
+    pub authority: Pubkey,
+    pub pending_authority: Pubkey,
+    pub timestamp: i64,
 }
 
-#[account]
-pub struct UserStakeAccount {
-    pub authority: Pubkey,
-    pub stakes: Vec<StakeRecord>,
+#[event]
+pub struct AuthorityTransferred {
+    pub config: Pubkey,
+    pub previous_authority: Pubkey,
+    pub new_authority: Pubkey,
+    pub timestamp: i64,
 }
 
-impl UserStakeAccount {
-    pub const DISCRIMINATOR: usize = 8;
-    pub const VECTOR_LENGTH_PREFIX: usize = 4;
-    const STAKE_RECORD_COUNT: usize = 1; // For more than 1 stake record at a time, user will pay
+/// Every admin-controlled setting after the change.
+#[event]
+pub struct ConfigUpdated {
+    pub config: Pubkey,
+    pub authority: Pubkey,
+    pub min_stake_period: i64,
+    pub reward_rate_bps: u16,
+    pub early_unstake_penalty_bps: u16,
+    pub penalty_linear_decay: bool,
+    pub penalty_destination: PenaltyDestination,
+    pub penalty_treasury: Pubkey,
+    pub unstake_cooldown: i64,
+    pub wind_down: bool,
+    pub paused: u8,
+    pub emergency: bool,
+    pub apy_on_ui_amount: bool,
+    pub lock_tiers: Vec<LockTier>,
+    pub timestamp: i64,
+}
 
-    pub const LEN: usize = Self::DISCRIMINATOR
-        + 32
-        + (Self::VECTOR_LENGTH_PREFIX + (StakeRecord::LEN * Self::STAKE_RECORD_COUNT));
+impl ConfigUpdated {
+    pub fn new(key: Pubkey, config: &Config, timestamp: i64) -> Self {
+        Self {
+            config: key,
+            authority: config.authority,
+            min_stake_period: config.min_stake_period,
+            reward_rate_bps: config.reward_rate_bps,
+            early_unstake_penalty_bps: config.early_unstake_penalty_bps,
+            penalty_linear_decay: config.penalty_linear_decay,
+            penalty_destination: config.penalty_destination,
+            penalty_treasury: config.penalty_treasury,
+            unstake_cooldown: config.unstake_cooldown,
+            wind_down: config.wind_down,
+            paused: config.paused,
+            emergency: config.emergency,
+            apy_on_ui_amount: config.apy_on_ui_amount,
+            lock_tiers: config.lock_tiers.clone(),
+            timestamp,
+        }
+    }
 }
 
 // ----------------------------------------------------------------------------------------------
@@ -932,11 +3829,517 @@ pub enum ErrorCode {
     TokenMintMismatch,
     #[msg("No transfer fee configuration found for this mint.")] // 6003
     NoTransferFeeConfig,
+    #[msg("Arithmetic overflow.")] // 6004
+    MathOverflow,
+    #[msg("Withdrawal exceeds the rewards not owed to stakers.")] // 6005
+    InsufficientSurplus,
+    #[msg("The pool is winding down.")] // 6006
+    PoolWindingDown,
+    #[msg("The pool is not winding down.")] // 6007
+    NotWindingDown,
+    #[msg("Stakers still have funds in the pool.")] // 6008
+    StakersRemaining,
+    #[msg("Not enough matured stake to unstake the requested amount.")] // 6009
+    InsufficientMaturedStake,
+    #[msg("No stake record with this id.")] // 6010
+    StakeRecordNotFound,
+    #[msg("The stake record has not passed the minimum stake period.")] // 6011
+    StakeNotMatured,
+    #[msg("Penalty cannot exceed 100%.")] // 6012
+    InvalidPenalty,
+    #[msg("Missing or wrong penalty treasury account.")] // 6013
+    InvalidTreasury,
+    #[msg("Unstaking requires a cooldown, use request_unstake.")] // 6014
+    CooldownRequired,
+    #[msg("The unstake cooldown has not elapsed yet.")] // 6015
+    CooldownNotElapsed,
+    #[msg("Pending withdrawal not found.")] // 6016
+    PendingWithdrawalNotFound,
+    #[msg("Cooldown cannot be negative.")] // 6017
+    InvalidCooldown,
+    #[msg("The pool is not the mint's withdraw withheld authority.")] // 6018
+    NotWithheldAuthority,
+    #[msg("The stake account still holds stakes or pending withdrawals.")] // 6019
+    StakeAccountNotEmpty,
+    #[msg("This instruction is paused.")] // 6020
+    Paused,
+    #[msg("Unknown pause flags.")] // 6021
+    InvalidPauseFlags,
+    #[msg("The pool is in emergency mode.")] // 6022
+    EmergencyMode,
+    #[msg("The pool is not in emergency mode.")] // 6023
+    NotEmergencyMode,
+    #[msg("Unknown or invalid lock tier.")] // 6024
+    InvalidLockTier,
+    #[msg("Too many lock tiers.")] // 6025
+    TooManyLockTiers,
+    #[msg("The new lock would end before the current one.")] // 6026
+    LockNotExtended,
+    #[msg("No rewards to compound.")] // 6027
+    NothingToCompound,
+    #[msg("Nothing left to stake after the transfer fee.")] // 6028
+    ZeroStakeAmount,
+    #[msg("Early unstaking is disabled until a penalty is configured.")] // 6029
+    EarlyUnstakeDisabled,
+    #[msg("Amount must be greater than zero.")] // 6030
+    ZeroAmount,
 }
 
 // ----------------------------------------------------------------------------------------------
 //                                  SEEDS
 // ----------------------------------------------------------------------------------------------
 
+// Pool PDAs are derived per mint and pool id, so one deployment can host any number of pools:
+//   config         = [CONFIG_PDA_SEED, token_mint, pool_id (u64 LE)]
+//   stake vault    = [STAKE_VAULT_SEED, config]    (staked principal only)
+//   reward vault   = [REWARD_VAULT_SEED, config]   (deposited rewards only)
+//   user stake acc = [USER_STAKE_SEED, config, user]
 pub const CONFIG_PDA_SEED: &[u8] = b"config-pda-1";
-pub const CONFIG_ATA_SEED: &[u8] = b"config-ata-1";
+pub const STAKE_VAULT_SEED: &[u8] = b"stake-vault-1";
+pub const REWARD_VAULT_SEED: &[u8] = b"reward-vault-1";
+pub const USER_STAKE_SEED: &[u8] = b"user-stake-1";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_TIER: LockTier = LockTier {
+        lock_period: 0,
+        multiplier_bps: BPS_DENOMINATOR as u16,
+    };
+
+    fn config(total_shares: u64, reward_budget: u64) -> Config {
+        Config {
+            authority: Pubkey::default(),
+            pending_authority: None,
+            min_stake_period: 0,
+            token_mint_address: Pubkey::default(),
+            pool_id: 0,
+            bump: 0,
+            stake_vault_bump: 0,
+            reward_vault_bump: 0,
+            reward_rate_bps: BPS_DENOMINATOR as u16,
+            acc_reward_per_share: 0,
+            last_reward_time: 0,
+            total_staked: total_shares,
+            total_shares,
+            reward_budget,
+            reward_liabilities: 0,
+            wind_down: false,
+            paused: 0,
+            emergency: false,
+            early_unstake_penalty_bps: 0,
+            penalty_linear_decay: false,
+            penalty_destination: PenaltyDestination::RewardVault,
+            penalty_treasury: Pubkey::default(),
+            unstake_cooldown: 0,
+            total_pending_withdrawals: 0,
+            compounded_principal: 0,
+            apy_on_ui_amount: false,
+            interest_rate_bps: 0,
+            lock_tiers: Vec::new(),
+        }
+    }
+
+    fn user_stake_account() -> UserStakeAccount {
+        UserStakeAccount {
+            authority: Pubkey::default(),
+            bump: 0,
+            staked_amount: 0,
+            shares: 0,
+            reward_debt: 0,
+            unclaimed_rewards: 0,
+            settled_acc_per_share: 0,
+            next_stake_id: 0,
+            stakes: Vec::new(),
+            pending_withdrawals: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn calculate_reward_rounds_down_net_of_debt() {
+        // 3 shares at 1.5 tokens per share
+        assert_eq!(calculate_reward(3, ACC_PRECISION * 3 / 2, 0).unwrap(), 4);
+        assert_eq!(
+            calculate_reward(3, ACC_PRECISION * 3 / 2, ACC_PRECISION).unwrap(),
+            3
+        );
+        assert!(calculate_reward(1, 0, 1).is_err());
+    }
+
+    mod accumulator {
+        use super::*;
+
+        #[test]
+        fn update_pool_emits_one_token_per_share_per_year() {
+            let mut config = config(1_000, u64::MAX);
+
+            config.update_pool(SECONDS_PER_YEAR as i64).unwrap();
+
+            assert_eq!(config.acc_reward_per_share, ACC_PRECISION);
+            assert_eq!(config.reward_liabilities, 1_000);
+            assert_eq!(config.reward_budget, u64::MAX - 1_000);
+            assert_eq!(config.last_reward_time, SECONDS_PER_YEAR as i64);
+        }
+
+        #[test]
+        fn update_pool_caps_emission_at_budget() {
+            let mut config = config(1_000, 10);
+
+            config.update_pool(SECONDS_PER_YEAR as i64).unwrap();
+
+            assert_eq!(config.reward_budget, 0);
+            assert_eq!(config.reward_liabilities, 10);
+            assert!(calculate_reward(1_000, config.acc_reward_per_share, 0).unwrap() <= 10);
+
+            // Nothing left to emit until more rewards are deposited
+            let acc_reward_per_share = config.acc_reward_per_share;
+            config.update_pool(2 * SECONDS_PER_YEAR as i64).unwrap();
+            assert_eq!(config.acc_reward_per_share, acc_reward_per_share);
+        }
+
+        #[test]
+        fn update_pool_charges_budget_rounded_up() {
+            let mut config = config(3, 100);
+
+            config.update_pool(1).unwrap();
+
+            // The per-share increment rounds down, the amount charged rounds up
+            assert_eq!(
+                config.acc_reward_per_share,
+                ACC_PRECISION / SECONDS_PER_YEAR
+            );
+            assert_eq!(config.reward_liabilities, 1);
+            assert_eq!(config.reward_budget, 99);
+            assert_eq!(
+                calculate_reward(3, config.acc_reward_per_share, 0).unwrap(),
+                0
+            );
+        }
+
+        #[test]
+        fn update_pool_without_shares_only_moves_the_clock() {
+            let mut config = config(0, 100);
+
+            config.update_pool(SECONDS_PER_YEAR as i64).unwrap();
+
+            assert_eq!(config.acc_reward_per_share, 0);
+            assert_eq!(config.reward_budget, 100);
+            assert_eq!(config.last_reward_time, SECONDS_PER_YEAR as i64);
+        }
+
+        #[test]
+        fn payouts_never_exceed_emissions() {
+            let mut config = config(0, 1_000_000);
+            let mut first = user_stake_account();
+            let mut second = user_stake_account();
+
+            let stake = first
+                .add_stake(
+                    333,
+                    None,
+                    &BASE_TIER,
+                    10_000,
+                    0,
+                    config.acc_reward_per_share,
+                )
+                .unwrap();
+            config.add_staked(stake.amount, stake.shares).unwrap();
+
+            config.update_pool(12_345).unwrap();
+            let stake = second
+                .add_stake(
+                    777,
+                    None,
+                    &BASE_TIER,
+                    10_000,
+                    12_345,
+                    config.acc_reward_per_share,
+                )
+                .unwrap();
+            config.add_staked(stake.amount, stake.shares).unwrap();
+
+            config.update_pool(SECONDS_PER_YEAR as i64).unwrap();
+
+            let paid = first.pending_reward(config.acc_reward_per_share).unwrap()
+                + second.pending_reward(config.acc_reward_per_share).unwrap();
+            assert!(paid <= config.reward_liabilities);
+            assert_eq!(config.reward_budget + config.reward_liabilities, 1_000_000);
+        }
+    }
+
+    mod partial_unstake {
+        use super::*;
+
+        #[test]
+        fn remove_matured_consumes_records_in_order() {
+            let mut account = user_stake_account();
+            let boosted = LockTier {
+                lock_period: 0,
+                multiplier_bps: 2 * BPS_DENOMINATOR as u16,
+            };
+            account
+                .add_stake(100, None, &BASE_TIER, 10_000, 0, 0)
+                .unwrap();
+            account
+                .add_stake(100, Some(0), &boosted, 10_000, 0, 0)
+                .unwrap();
+
+            let removed_shares = account.remove_matured(150, UnstakeOrder::Fifo, 0).unwrap();
+
+            assert_eq!(removed_shares, 100 + 100);
+            assert_eq!(account.stakes.len(), 1);
+            assert_eq!(account.stakes[0].id, 1);
+            assert_eq!(account.stakes[0].amount, 50);
+            assert_eq!(account.stakes[0].shares, 100);
+            assert_eq!(account.staked_amount, 50);
+            assert_eq!(account.shares, 100);
+        }
+
+        #[test]
+        fn remove_matured_leaves_immature_and_empty_records_alone() {
+            let mut account = user_stake_account();
+            let locked = LockTier {
+                lock_period: 1,
+                multiplier_bps: BPS_DENOMINATOR as u16,
+            };
+            account.add_stake(100, None, &locked, 10_000, 0, 0).unwrap();
+            account.stakes.push(StakeRecord {
+                id: 1,
+                amount: 0,
+                shares: 0,
+                timestamp: 0,
+                tier: None,
+                reward_rate_bps: 10_000,
+                unlock_time: 0,
+                entry_acc_per_share: 0,
+            });
+
+            assert_eq!(
+                account
+                    .remove_matured(1, UnstakeOrder::Fifo, 0)
+                    .unwrap_err(),
+                ErrorCode::InsufficientMaturedStake.into()
+            );
+            assert_eq!(
+                account
+                    .remove_matured(100, UnstakeOrder::Lifo, SECONDS_PER_DAY)
+                    .unwrap(),
+                100
+            );
+            assert!(account.stakes.is_empty());
+        }
+    }
+
+    mod early_unstake {
+        use super::*;
+
+        #[test]
+        fn early_unstake_penalty_rounds_up() {
+            let mut config = config(0, 0);
+            config.early_unstake_penalty_bps = 1_000;
+
+            assert_eq!(config.early_unstake_penalty(1_001, 50, 100).unwrap(), 101);
+            assert_eq!(config.early_unstake_penalty(1_001, 0, 100).unwrap(), 0);
+
+            config.penalty_linear_decay = true;
+            assert_eq!(config.early_unstake_penalty(1_000, 50, 100).unwrap(), 50);
+            assert_eq!(config.early_unstake_penalty(1_000, 1, 100).unwrap(), 1);
+            assert_eq!(config.early_unstake_penalty(1_000, 200, 100).unwrap(), 100);
+        }
+    }
+
+    mod interest_bearing {
+        use super::*;
+
+        #[test]
+        fn emission_rate_tops_up_interest_to_the_target_apy() {
+            let mut config = config(0, 0);
+            config.reward_rate_bps = 1_000;
+            config.interest_rate_bps = 300;
+            assert_eq!(config.emission_rate_bps(), 1_000);
+
+            config.apy_on_ui_amount = true;
+            assert_eq!(config.emission_rate_bps(), 700);
+
+            config.interest_rate_bps = 1_500;
+            assert_eq!(config.emission_rate_bps(), 0);
+        }
+
+        #[test]
+        fn emission_rate_saturates_with_negative_interest() {
+            let mut config = config(0, 0);
+            config.apy_on_ui_amount = true;
+            config.reward_rate_bps = 65_000;
+            config.interest_rate_bps = -1_000;
+
+            assert_eq!(config.emission_rate_bps(), u16::MAX);
+        }
+    }
+
+    mod account_sizing {
+        use super::*;
+
+        fn serialized_len(account: &UserStakeAccount) -> usize {
+            let mut data = Vec::new();
+            account.try_serialize(&mut data).unwrap();
+            data.len()
+        }
+
+        /// What `stake` reallocs to before adding its record. A tiered record serializes to the
+        /// full `StakeRecord::LEN`, so it fills that space exactly.
+        fn stake(account: &mut UserStakeAccount, amount: u64) -> usize {
+            let space = UserStakeAccount::space(
+                account.stakes.len() + 1,
+                account.pending_withdrawals.len(),
+            );
+            account
+                .add_stake(amount, Some(0), &BASE_TIER, 10_000, 0, 0)
+                .unwrap();
+            space
+        }
+
+        #[test]
+        fn every_stake_fits_the_space_it_reallocs() {
+            let mut account = user_stake_account();
+            assert_eq!(serialized_len(&account), UserStakeAccount::LEN);
+
+            for _ in 0..3 {
+                let space = stake(&mut account, 100);
+                assert_eq!(serialized_len(&account), space);
+            }
+
+            // `request_unstake` queues a withdrawal and drops the emptied record
+            let space = UserStakeAccount::space(
+                account.stakes.len(),
+                account.pending_withdrawals.len() + 1,
+            );
+            account.remove_matured(100, UnstakeOrder::Fifo, 0).unwrap();
+            account.add_pending_withdrawal(100, 0).unwrap();
+            assert_eq!(account.stakes.len(), 2);
+            assert!(serialized_len(&account) <= space);
+
+            let space = stake(&mut account, 100);
+            assert_eq!(account.stakes.len(), 3);
+            assert_eq!(account.pending_withdrawals.len(), 1);
+            assert_eq!(serialized_len(&account), space);
+        }
+    }
+
+    mod relock {
+        use super::*;
+
+        const LOCKED: LockTier = LockTier {
+            lock_period: 100,
+            multiplier_bps: BPS_DENOMINATOR as u16,
+        };
+
+        const BOOSTED: LockTier = LockTier {
+            lock_period: 200,
+            multiplier_bps: 2 * BPS_DENOMINATOR as u16,
+        };
+
+        #[test]
+        fn relock_stake_keeps_the_record_and_moves_its_reward_to_unclaimed() {
+            let mut account = user_stake_account();
+            account.add_stake(100, None, &LOCKED, 10_000, 0, 0).unwrap();
+            account.add_stake(100, None, &LOCKED, 10_000, 0, 0).unwrap();
+
+            let (previous, stake, reward) = account
+                .relock_stake(0, Some(0), &BOOSTED, 10_000, 50, ACC_PRECISION)
+                .unwrap();
+
+            assert_eq!(previous.unlock_time, 100 * SECONDS_PER_DAY);
+            assert_eq!(reward, 100);
+            assert_eq!(stake.id, 0);
+            assert_eq!(stake.shares, 200);
+            assert_eq!(stake.unlock_time, 50 + 200 * SECONDS_PER_DAY);
+            assert_eq!(account.stakes[0].shares, stake.shares);
+            assert_eq!(account.stakes[0].unlock_time, stake.unlock_time);
+            assert_eq!(account.stakes.len(), 2);
+            assert_eq!(account.staked_amount, 200);
+            assert_eq!(account.shares, 300);
+            assert_eq!(account.unclaimed_rewards, 100);
+            // The relocked record starts from scratch, the other one still has its reward
+            assert_eq!(account.pending_reward(ACC_PRECISION).unwrap(), 200);
+        }
+
+        #[test]
+        fn relock_stake_rejects_a_shorter_lock() {
+            let mut account = user_stake_account();
+            account
+                .add_stake(100, None, &BOOSTED, 10_000, 0, 0)
+                .unwrap();
+
+            assert_eq!(
+                account
+                    .relock_stake(0, None, &LOCKED, 10_000, 50, ACC_PRECISION)
+                    .map(|_| ())
+                    .unwrap_err(),
+                ErrorCode::LockNotExtended.into()
+            );
+            assert_eq!(account.unclaimed_rewards, 0);
+            assert_eq!(account.stakes[0].unlock_time, 200 * SECONDS_PER_DAY);
+        }
+
+        #[test]
+        fn take_unclaimed_if_last_waits_for_the_last_record() {
+            let mut account = user_stake_account();
+            account.add_stake(100, None, &LOCKED, 10_000, 0, 0).unwrap();
+            account
+                .relock_stake(0, None, &LOCKED, 10_000, 50, ACC_PRECISION)
+                .unwrap();
+
+            assert_eq!(account.take_unclaimed_if_last(), 0);
+            assert_eq!(account.unclaimed_rewards, 100);
+
+            account.remove_stake(0, ACC_PRECISION).unwrap();
+            assert_eq!(account.take_unclaimed_if_last(), 100);
+            assert_eq!(account.unclaimed_rewards, 0);
+        }
+    }
+
+    mod compound {
+        use super::*;
+
+        #[test]
+        fn compound_restakes_pending_rewards_as_principal() {
+            let mut config = config(100, 0);
+            let mut account = user_stake_account();
+            account
+                .add_stake(100, None, &BASE_TIER, 10_000, 0, 0)
+                .unwrap();
+            config.acc_reward_per_share = ACC_PRECISION;
+            config.reward_liabilities = 100;
+
+            let stake = config.compound(&mut account, None, &BASE_TIER, 0).unwrap();
+
+            assert_eq!(stake.amount, 100);
+            assert_eq!(stake.shares, 100);
+            assert_eq!(config.reward_liabilities, 0);
+            assert_eq!(config.compounded_principal, 100);
+            assert_eq!(config.total_staked, 200);
+            assert_eq!(config.total_shares, 200);
+            assert_eq!(account.staked_amount, 200);
+            assert_eq!(account.pending_reward(ACC_PRECISION).unwrap(), 0);
+            assert_eq!(
+                config
+                    .compound(&mut account, None, &BASE_TIER, 0)
+                    .map(|_| ())
+                    .unwrap_err(),
+                ErrorCode::NothingToCompound.into()
+            );
+        }
+
+        #[test]
+        fn take_principal_spends_compounded_principal_first() {
+            let mut config = config(0, 0);
+            config.compounded_principal = 30;
+
+            assert_eq!(config.take_principal(20).unwrap(), (0, 20));
+            assert_eq!(config.take_principal(100).unwrap(), (90, 10));
+            assert_eq!(config.compounded_principal, 0);
+            assert_eq!(config.take_principal(50).unwrap(), (50, 0));
+        }
+    }
+}
@@ -13,11 +13,15 @@ dotenv.config();
 
 const TOKEN_MINT = process.env.TOKEN_MINT;
 const CONFIG_PDA_SEED = process.env.CONFIG_PDA_SEED;
-const CONFIG_ATA_SEED = process.env.CONFIG_ATA_SEED;
+const STAKE_VAULT_SEED = process.env.STAKE_VAULT_SEED;
+const REWARD_VAULT_SEED = process.env.REWARD_VAULT_SEED;
+const USER_STAKE_SEED = process.env.USER_STAKE_SEED;
+const POOL_ID = new anchor.BN(process.env.POOL_ID ?? 0);
 const AUTHORITY_WALLET: number[] = JSON.parse(process.env.AUTHORITY_WALLET);
 
 let CONFIG_PDA = { configPda: null, configPdaBump: null };
-let CONFIG_ATA = { configAta: null, configAtaBump: null };
+let STAKE_VAULT = { stakeVault: null, stakeVaultBump: null };
+let REWARD_VAULT = { rewardVault: null, rewardVaultBump: null };
 
 // -------------------------------------------------------------------------------------------------
 
@@ -37,17 +41,26 @@ describe("token-2022-staking", async () => {
   const authorityPublicKey = authorityWallet.publicKey;
 
   [CONFIG_PDA.configPda, CONFIG_PDA.configPdaBump] = anchor.web3.PublicKey.findProgramAddressSync(
-    [Buffer.from(CONFIG_PDA_SEED)],
+    [Buffer.from(CONFIG_PDA_SEED), TOKEN_MINT_PUBLIC_KEY.toBuffer(), POOL_ID.toArrayLike(Buffer, "le", 8)],
     program.programId
   );
 
-  [CONFIG_ATA.configAta, CONFIG_ATA.configAtaBump] = anchor.web3.PublicKey.findProgramAddressSync(
-    [Buffer.from(CONFIG_ATA_SEED)],
+  [STAKE_VAULT.stakeVault, STAKE_VAULT.stakeVaultBump] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from(STAKE_VAULT_SEED), CONFIG_PDA.configPda.toBuffer()],
+    program.programId
+  );
+
+  [REWARD_VAULT.rewardVault, REWARD_VAULT.rewardVaultBump] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from(REWARD_VAULT_SEED), CONFIG_PDA.configPda.toBuffer()],
     program.programId
   );
 
   const [userStakeAccountPda, userStakeAccountPdaBump] = anchor.web3.PublicKey.findProgramAddressSync(
-    [new anchor.web3.PublicKey("HEWbAoGrvmYerTHZ5vbv887DmsMiB58c4skiiE7P6kvo").toBuffer()],
+    [
+      Buffer.from(USER_STAKE_SEED),
+      CONFIG_PDA.configPda.toBuffer(),
+      new anchor.web3.PublicKey("HEWbAoGrvmYerTHZ5vbv887DmsMiB58c4skiiE7P6kvo").toBuffer(),
+    ],
     program.programId
   );
 
@@ -60,14 +73,14 @@ describe("token-2022-staking", async () => {
 
   // console.log({ B: TOKEN_MINT_PUBLIC_KEY.toBase58(), A: authorityAssociatedTokenAccount.toBase58() });
 
-  // const configAtaAccount = await getAccount(provider.connection, CONFIG_ATA.configAta, "confirmed", TOKEN_2022_PROGRAM_ID);
+  // const rewardVaultAccount = await getAccount(provider.connection, REWARD_VAULT.rewardVault, "confirmed", TOKEN_2022_PROGRAM_ID);
 
   // console.log({
   //   ConfigPda: CONFIG_PDA.configPda.toBase58(),
-  //   Address: configAtaAccount.address.toBase58(),
-  //   mint: configAtaAccount.mint.toBase58(),
-  //   owner: configAtaAccount.owner.toBase58(),
-  //   amount: configAtaAccount.amount.toString(),
+  //   Address: rewardVaultAccount.address.toBase58(),
+  //   mint: rewardVaultAccount.mint.toBase58(),
+  //   owner: rewardVaultAccount.owner.toBase58(),
+  //   amount: rewardVaultAccount.amount.toString(),
   // });
 
   // -------------------------------------------------------------------------------------------------
@@ -76,14 +89,14 @@ describe("token-2022-staking", async () => {
 
   it("Is initialized!", async () => {
     const minimumStakePeriod = 30;
-    const tokenDecimals = 9;
-    const taxPercentage = 3;
+    const rewardRateBps = 1500; // 15% APY
 
     const tx = await program.methods
-      .initialize(new anchor.BN(minimumStakePeriod), tokenDecimals, taxPercentage)
+      .initialize(POOL_ID, new anchor.BN(minimumStakePeriod), rewardRateBps)
       .accounts({
         authority: authorityPublicKey,
         tokenMint: authorityPublicKey,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
       })
       .signers([authorityWallet])
       .rpc();
@@ -110,6 +123,85 @@ describe("token-2022-staking", async () => {
   //   console.log("Your transaction signature", tx);
   // });
 
+  // it("Update Reward Rate", async () => {
+  //   const tx = await program.methods
+  //     .updateRewardRate(2000)
+  //     .accounts({
+  //       config: CONFIG_PDA.configPda,
+  //       authority: authorityPublicKey,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
+  // it("Update APY On UI Amount", async () => {
+  //   const tx = await program.methods
+  //     .updateApyOnUiAmount(true)
+  //     .accounts({
+  //       authority: authorityPublicKey,
+  //       tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //       tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
+  // it("Propose Authority", async () => {
+  //   const tx = await program.methods
+  //     .proposeAuthority(authorityPublicKey)
+  //     .accounts({
+  //       config: CONFIG_PDA.configPda,
+  //       authority: authorityPublicKey,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
+  // it("Accept Authority", async () => {
+  //   const tx = await program.methods
+  //     .acceptAuthority()
+  //     .accounts({
+  //       config: CONFIG_PDA.configPda,
+  //       newAuthority: authorityPublicKey,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
+  // it("Set Pause Flags", async () => {
+  //   const PAUSE_STAKE = 1 << 0;
+  //   const tx = await program.methods
+  //     .setPauseFlags(PAUSE_STAKE)
+  //     .accounts({
+  //       config: CONFIG_PDA.configPda,
+  //       authority: authorityPublicKey,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
+  // it("Set Lock Tiers", async () => {
+  //   const tx = await program.methods
+  //     .setLockTiers([
+  //       { lockPeriod: new anchor.BN(30), multiplierBps: 10000 },
+  //       { lockPeriod: new anchor.BN(90), multiplierBps: 12500 },
+  //       { lockPeriod: new anchor.BN(180), multiplierBps: 15000 },
+  //       { lockPeriod: new anchor.BN(365), multiplierBps: 20000 },
+  //     ])
+  //     .accounts({
+  //       config: CONFIG_PDA.configPda,
+  //       authority: authorityPublicKey,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
+
   // it("Deposit Rewards", async () => {
   //   const tx = await program.methods
   //     .depositRewards(new anchor.BN(100 * Math.pow(10, 9)))
@@ -117,6 +209,7 @@ describe("token-2022-staking", async () => {
   //       depositor: authorityPublicKey,
   //       depositorAta: authorityAssociatedTokenAccount,
   //       tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //       tokenProgram: TOKEN_2022_PROGRAM_ID,
   //     })
   //     .signers([authorityWallet])
   //     .rpc();
@@ -125,61 +218,120 @@ describe("token-2022-staking", async () => {
 
   // // it("Withdraw Rewards", async () => {
   // const tx = await program.methods
-  //   .withdraw(CONFIG_PDA.configPdaBump)
+  //   .withdraw(new anchor.BN(10 * Math.pow(10, 9)))
   //   .accounts({
   //     authority: authorityPublicKey,
   //     authorityAta: authorityAssociatedTokenAccount,
   //     tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //     tokenProgram: TOKEN_2022_PROGRAM_ID,
   //   })
   //   .signers([authorityWallet])
   //   .rpc();
   // //   console.log("Your transaction signature", tx);
   // // });
 
-  // it("Stake", async () => {
-  //   try {
-  //     const userStakeAccount = await program.account.userStakeAccount.fetch(userStakeAccountPda);
+  // it("Harvest Withheld Fees", async () => {
+  //   const tx = await program.methods
+  //     .harvestWithheldFees()
+  //     .accounts({
+  //       tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //       tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //     })
+  //     .remainingAccounts([{ pubkey: authorityAssociatedTokenAccount, isSigner: false, isWritable: true }])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
 
-  //     console.log(userStakeAccountPda);
+  // it("Withdraw Withheld Fees", async () => {
+  //   const tx = await program.methods
+  //     .withdrawWithheldFees()
+  //     .accounts({
+  //       authority: authorityPublicKey,
+  //       tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //       tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //     })
+  //     .signers([authorityWallet])
+  //     .rpc();
+  //   console.log("Your transaction signature", tx);
+  // });
 
-  //     // Account Exists
+  // it("Open Stake Account", async () => {
+  //   try {
   //     const tx = await program.methods
-  //       .stakeReallocx(new anchor.BN(10 * Math.pow(10, 9)))
+  //       .openStakeAccount()
+  //       .accounts({
+  //         config: CONFIG_PDA.configPda,
+  //         user: authorityPublicKey,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Stake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .stake(new anchor.BN(10 * Math.pow(10, 9)), null)
   //       .accounts({
   //         user: authorityPublicKey,
   //         userAta: authorityAssociatedTokenAccount,
   //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
   //       })
   //       .signers([authorityWallet])
   //       .rpc();
   //     console.log("Your transaction signature", tx);
   //   } catch (error) {
   //     console.log(error);
+  //   }
+  // });
 
-  //     // Not Exists
-  //     if (error.message && error.message.includes("Account does not exist")) {
-  //       const tx = await program.methods
-  //         .stake(new anchor.BN(10 * Math.pow(10, 9)))
-  //         .accounts({
-  //           user: authorityPublicKey,
-  //           userAta: authorityAssociatedTokenAccount,
-  //           tokenMint: TOKEN_MINT_PUBLIC_KEY,
-  //         })
-  //         .signers([authorityWallet])
-  //         .rpc();
-  //       console.log("Your transaction signature", tx);
-  //     }
+  // it("Extend Stake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .extendStake(new anchor.BN(0), 3)
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
   //   }
   // });
 
   // it("Claim Rewards", async () => {
   //   try {
   //     const tx = await program.methods
-  //       .claimRewards(CONFIG_PDA.configPdaBump)
+  //       .claimRewards()
   //       .accounts({
   //         user: authorityPublicKey,
   //         userAta: authorityAssociatedTokenAccount,
   //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Compound", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .compound(null)
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
   //       })
   //       .signers([authorityWallet])
   //       .rpc();
@@ -192,11 +344,136 @@ describe("token-2022-staking", async () => {
   // it("Unstake", async () => {
   //   try {
   //     const tx = await program.methods
-  //       .unstake(CONFIG_PDA.configPdaBump)
+  //       .unstake(true)
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         userAta: authorityAssociatedTokenAccount,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Unstake Amount", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .unstakeAmount(new anchor.BN(5 * Math.pow(10, 9)), { fifo: {} })
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         userAta: authorityAssociatedTokenAccount,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Early Unstake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .earlyUnstake(new anchor.BN(0))
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         userAta: authorityAssociatedTokenAccount,
+  //         treasury: null,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Request Unstake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .requestUnstake(new anchor.BN(5 * Math.pow(10, 9)), { fifo: {} })
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         userAta: authorityAssociatedTokenAccount,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Complete Unstake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .completeUnstake(new anchor.BN(1))
   //       .accounts({
   //         user: authorityPublicKey,
   //         userAta: authorityAssociatedTokenAccount,
   //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Cancel Unstake", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .cancelUnstake(new anchor.BN(1))
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Emergency Withdraw", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .emergencyWithdraw()
+  //       .accounts({
+  //         user: authorityPublicKey,
+  //         userAta: authorityAssociatedTokenAccount,
+  //         tokenMint: TOKEN_MINT_PUBLIC_KEY,
+  //         tokenProgram: TOKEN_2022_PROGRAM_ID,
+  //       })
+  //       .signers([authorityWallet])
+  //       .rpc();
+  //     console.log("Your transaction signature", tx);
+  //   } catch (error) {
+  //     console.log(error);
+  //   }
+  // });
+
+  // it("Close Stake Account", async () => {
+  //   try {
+  //     const tx = await program.methods
+  //       .closeStakeAccount()
+  //       .accounts({
+  //         config: CONFIG_PDA.configPda,
+  //         user: authorityPublicKey,
   //       })
   //       .signers([authorityWallet])
   //       .rpc();
@@ -212,9 +489,20 @@ describe("token-2022-staking", async () => {
 
       console.log("User Stake Account: ", {
         authority: userStakeAccount.authority.toBase58(),
+        unclaimedRewards: userStakeAccount.unclaimedRewards.toString(),
         stakes: userStakeAccount.stakes.map((stake) => ({
+          id: stake.id.toString(),
           amount: stake.amount.toString(),
+          shares: stake.shares.toString(),
+          tier: stake.tier,
+          rewardRateBps: stake.rewardRateBps,
           timestamp: new Date(stake.timestamp.toNumber() * 1000).toLocaleString(), // converting UNIX timestamp to readable date
+          unlockTime: new Date(stake.unlockTime.toNumber() * 1000).toLocaleString(),
+        })),
+        pendingWithdrawals: userStakeAccount.pendingWithdrawals.map((withdrawal) => ({
+          id: withdrawal.id.toString(),
+          amount: withdrawal.amount.toString(),
+          unlockTime: new Date(withdrawal.unlockTime.toNumber() * 1000).toLocaleString(),
         })),
       });
     } catch (error) {